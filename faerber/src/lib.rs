@@ -1,18 +1,21 @@
-pub type ColorScheme = HashMap<String, Palette>;
+/// Flavours by name, sorted so that the default flavour is the same on every run.
+pub type ColorScheme = BTreeMap<String, Palette>;
 pub type Library = HashMap<String, ColorScheme>;
-pub type Palette = HashMap<String, u32>;
+/// Colors by name, sorted by name so that palette indices are the same on every run.
+pub type Palette = BTreeMap<String, u32>;
 use faerber_lib::custom_lab::Lab;
+use faerber_lib::FaerberError;
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 lazy_static::lazy_static! {
     pub static ref LIBRARY: Library = {
         let mut library: Library = HashMap::new();
-        library.insert("catppuccin".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/catppuccin.json")).unwrap()));
-        library.insert("dracula".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/dracula.json")).unwrap()));
-        library.insert("gruvbox".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/gruvbox.json")).unwrap()));
-        library.insert("nord".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/nord.json")).unwrap()));
-        library.insert("solarized".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/solarized.json")).unwrap()));
+        library.insert("catppuccin".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/catppuccin.json")).unwrap()).unwrap());
+        library.insert("dracula".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/dracula.json")).unwrap()).unwrap());
+        library.insert("gruvbox".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/gruvbox.json")).unwrap()).unwrap());
+        library.insert("nord".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/nord.json")).unwrap()).unwrap());
+        library.insert("solarized".to_string(), parse_colorscheme(serde_json::from_str(include_str!("../palettes/solarized.json")).unwrap()).unwrap());
         library
     };
 }
@@ -30,21 +33,75 @@ pub fn get_labs(palette: Palette) -> Vec<Lab> {
         .collect();
 }
 
-pub fn parse_colorscheme(json: Value) -> ColorScheme {
-    let mut color_scheme: ColorScheme = HashMap::new();
+/// # Errors
+///
+/// Returns an error if the JSON is not an object of flavours mapping color names to hex strings,
+/// or if it has no flavours.
+pub fn parse_colorscheme(json: Value) -> Result<ColorScheme, FaerberError> {
+    let mut color_scheme = ColorScheme::new();
+    let invalid = |value: &Value| FaerberError::InvalidColor(value.to_string());
 
-    json.as_object().unwrap().iter().for_each(|(k, v)| {
-        let palette: Palette = v
+    let flavours = json.as_object().ok_or_else(|| {
+        FaerberError::InvalidPalette("expected an object of flavours".to_string())
+    })?;
+    if flavours.is_empty() {
+        return Err(FaerberError::InvalidPalette(
+            "it has no flavours".to_string(),
+        ));
+    }
+    for (k, v) in flavours {
+        let palette = v
             .as_object()
-            .unwrap()
+            .ok_or_else(|| {
+                FaerberError::InvalidPalette(format!(
+                    "flavour {k} should map color names to colors"
+                ))
+            })?
             .iter()
             .map(|(k, v)| {
-                let hex = v.as_str().unwrap().trim_start_matches('#');
-                (k.to_string(), u32::from_str_radix(hex, 16).unwrap())
+                let hex = v
+                    .as_str()
+                    .ok_or_else(|| invalid(v))?
+                    .trim_start_matches('#');
+                let color = u32::from_str_radix(hex, 16).map_err(|_| invalid(v))?;
+                Ok((k.to_string(), color))
             })
-            .collect();
+            .collect::<Result<Palette, FaerberError>>()?;
 
         color_scheme.insert(k.to_string().replace(' ', "_").to_lowercase(), palette);
-    });
-    color_scheme
+    }
+    Ok(color_scheme)
+}
+
+/// Resolves an object mapping source colors to names of palette colors,
+/// like `{"#ff0000": "red"}`, into the palette colors to use for those sources.
+///
+/// # Errors
+///
+/// Returns an error if the JSON is not such an object, if a source color can't be parsed,
+/// or if a name is not part of the palette.
+pub fn parse_overrides(
+    json: &Value,
+    palette: &Palette,
+) -> Result<HashMap<[u8; 3], Lab>, FaerberError> {
+    let invalid = |value: &Value| FaerberError::InvalidColor(value.to_string());
+
+    json.as_object()
+        .ok_or_else(|| invalid(json))?
+        .iter()
+        .map(|(source, name)| {
+            let hex = source.trim_start_matches('#');
+            let source = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)
+                .ok_or_else(|| FaerberError::InvalidColor(source.to_string()))?;
+            let target = name
+                .as_str()
+                .and_then(|name| palette.get(name))
+                .ok_or_else(|| FaerberError::UnknownPaletteColor(name.to_string()))?;
+            let [_, r, g, b] = source.to_be_bytes();
+            let [_, tr, tg, tb] = target.to_be_bytes();
+            Ok(([r, g, b], Lab::from_rgb(&[tr, tg, tb])))
+        })
+        .collect()
 }
@@ -9,16 +9,29 @@
     // clippy::expect_used,
 )]
 
-use clap::ArgGroup;
+use clap::builder::{PossibleValue, TypedValueParser};
+use clap::parser::ValueSource;
 use clap::{value_parser, Arg, ArgAction, Command, ValueEnum, ValueHint};
+use clap::{ArgGroup, ArgMatches};
 use clap_complete::{generate, Generator, Shell};
-use faerber::{get_labs, parse_colorscheme, ColorScheme, Palette, LIBRARY};
-use faerber_lib::DEMethod;
+use faerber::{get_labs, parse_colorscheme, parse_overrides, ColorScheme, Palette, LIBRARY};
+use faerber_lib::dither::{ErrorDiffusion, ThresholdMap};
+use faerber_lib::mask::apply_mask;
 use faerber_lib::Lab;
-use image::RgbaImage;
+use faerber_lib::{
+    AlphaOptions, BandConverter, ColorDistance, ColorSpace, DEMethod, FaerberError, IccProfile,
+    Indexed, LabPixel, Lut, Metric, Overrides, ProtectedColors, Quantizer, RampSpace, Region, Slic,
+    VectorOptions, Weighted,
+};
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::{DynamicImage, GrayImage, ImageBuffer, ImageDecoder, ImageFormat, RgbaImage};
+use std::collections::HashMap;
+use std::error::Error;
+use std::ffi::OsStr;
 use std::fs::{read_to_string, File};
 use std::io;
-use std::io::{Cursor, Write};
+use std::io::{BufReader, BufWriter, Cursor, Write};
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -30,21 +43,142 @@ enum CliDeltaMethods {
     De94t,
     De94g,
     De2000,
+    /// CMC l:c, with the weights set by --cmc-l and --cmc-c
+    Cmc,
+    /// Absolute lightness difference plus the a*/b* distance in CIELAB
+    Hyab,
 }
 
-impl From<CliDeltaMethods> for DEMethod {
-    fn from(val: CliDeltaMethods) -> Self {
+impl CliDeltaMethods {
+    fn metric(self, cmc_lightness: f32, cmc_chroma: f32) -> Metric {
+        match self {
+            Self::De76 => DEMethod::DE1976.into(),
+            Self::De94t => DEMethod::DE1994T.into(),
+            Self::De94g => DEMethod::DE1994G.into(),
+            Self::De2000 => DEMethod::DE2000.into(),
+            Self::Cmc => DEMethod::DECMC(cmc_lightness, cmc_chroma).into(),
+            Self::Hyab => Metric::HyAB,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, ValueEnum)]
+enum CliSpace {
+    Lab,
+    Oklab,
+    Cam16Ucs,
+    Din99o,
+    Jzazbz,
+}
+
+impl From<CliSpace> for ColorSpace {
+    fn from(val: CliSpace) -> Self {
+        match val {
+            CliSpace::Lab => Self::CieLab,
+            CliSpace::Oklab => Self::Oklab,
+            CliSpace::Cam16Ucs => Self::Cam16Ucs,
+            CliSpace::Din99o => Self::Din99o,
+            CliSpace::Jzazbz => Self::Jzazbz,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, ValueEnum)]
+enum CliRampSpace {
+    Lab,
+    Oklab,
+}
+
+impl From<CliRampSpace> for RampSpace {
+    fn from(val: CliRampSpace) -> Self {
         match val {
-            CliDeltaMethods::De76 => Self::DE1976,
-            CliDeltaMethods::De94t => Self::DE1994T,
-            CliDeltaMethods::De94g => Self::DE1994G,
-            CliDeltaMethods::De2000 => Self::DE2000,
+            CliRampSpace::Lab => Self::CieLab,
+            CliRampSpace::Oklab => Self::Oklab,
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, ValueEnum)]
+enum CliQuantizer {
+    /// Split the colors at the median of their widest channel
+    MedianCut,
+    /// Cluster the colors in CIELAB, the most representative palette
+    KMeans,
+    /// Merge the least used colors of an octree, keeps rare accent colors
+    Octree,
+}
+
+impl From<CliQuantizer> for Quantizer {
+    fn from(val: CliQuantizer) -> Self {
+        match val {
+            CliQuantizer::MedianCut => Self::MedianCut,
+            CliQuantizer::KMeans => Self::KMeans,
+            CliQuantizer::Octree => Self::Octree,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, ValueEnum)]
+enum CliDither {
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    Bayer16,
+    BlueNoise,
+}
+
+impl CliDither {
+    fn convert(
+        self,
+        img: &RgbaImage,
+        method: &dyn ColorDistance,
+        labs: &[Lab],
+        strength: f32,
+        protected: &ProtectedColors,
+        overrides: &Overrides,
+    ) -> Indexed {
+        let error_diffusion = |kernel| {
+            faerber_lib::dither::error_diffusion(
+                img, method, labs, kernel, strength, true, protected,
+            )
+        };
+        let ordered = |map| faerber_lib::dither::ordered(img, method, labs, map, strength);
+
+        match self {
+            Self::None => faerber_lib::convert_indexed_with_overrides(img, method, labs, overrides),
+            Self::FloydSteinberg => error_diffusion(ErrorDiffusion::FloydSteinberg),
+            Self::Atkinson => error_diffusion(ErrorDiffusion::Atkinson),
+            Self::JarvisJudiceNinke => error_diffusion(ErrorDiffusion::JarvisJudiceNinke),
+            Self::Stucki => error_diffusion(ErrorDiffusion::Stucki),
+            Self::Sierra => error_diffusion(ErrorDiffusion::Sierra),
+            Self::Bayer2 => ordered(ThresholdMap::Bayer2),
+            Self::Bayer4 => ordered(ThresholdMap::Bayer4),
+            Self::Bayer8 => ordered(ThresholdMap::Bayer8),
+            Self::Bayer16 => ordered(ThresholdMap::Bayer16),
+            Self::BlueNoise => ordered(ThresholdMap::BlueNoise),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, ValueEnum)]
+enum CliMapping {
+    /// Replace every pixel with its closest palette color
+    Nearest,
+    /// Keep the lightness of every pixel and take only its hue and chroma from the palette
+    PreserveLightness,
+    /// Mix the two closest palette colors of every pixel, keeping edges and gradients smooth
+    Soft,
+    /// Split the image into regions of similar color and give every region one palette color
+    Regions,
+}
+
 fn build_cli() -> Command {
-    let palettes = LIBRARY.keys().map(|s| s.to_lowercase()).collect::<Vec<_>>();
     let flavours = LIBRARY
         .iter()
         .flat_map(|(_k, v)| v.keys().map(|s| s.to_lowercase()).collect::<Vec<_>>())
@@ -66,20 +200,64 @@ fn build_cli() -> Command {
             Arg::new("palette")
                 .short('p')
                 .long("palette")
-                .value_parser(palettes)
+                .help("A built-in palette, or a JSON file like the ones `faerber extract` writes")
+                .value_parser(PaletteParser)
+                .value_hint(ValueHint::FilePath)
                 .default_value("catppuccin"),
             Arg::new("flavour")
                 .short('f')
                 .long("flavour")
                 .value_parser(flavours),
+            Arg::new("expand")
+                .long("expand")
+                .value_name("SHADES")
+                .help("Add this many shades between every palette color and the darkest and lightest one")
+                .value_parser(value_parser!(usize)),
+            Arg::new("expand_space")
+                .long("expand-space")
+                .help("Color space to interpolate the shades of --expand in")
+                .value_parser(value_parser!(CliRampSpace))
+                .default_value("oklab"),
+        ])
+        .args(metric_args())
+        .args([
+            Arg::new("dither")
+                .short('d')
+                .long("dither")
+                .value_parser(value_parser!(CliDither))
+                .default_value("none"),
+            Arg::new("dither_strength")
+                .long("dither-strength")
+                .help("How much of the quantization error to spread, from 0.0 to 1.0")
+                .value_parser(parse_strength)
+                .default_value("1.0"),
         ])
         .arg(
-            Arg::new("method")
-                .short('m')
-                .long("method")
-                .value_parser(value_parser!(CliDeltaMethods))
-                .default_value("de2000"),
+            Arg::new("mapping")
+                .long("mapping")
+                .value_parser(value_parser!(CliMapping))
+                .default_value("nearest"),
         )
+        .arg(
+            Arg::new("strength")
+                .long("strength")
+                .help("How far to move colors toward the palette, from 0.0 to 1.0")
+                .value_parser(parse_strength)
+                .default_value("1.0"),
+        )
+        .args(alpha_args())
+        .args(despeckle_args())
+        .args(region_args())
+        .args(mask_args())
+        .args(protect_args())
+        .arg(
+            Arg::new("lut")
+                .long("lut")
+                .help("Convert through a precomputed lookup table, faster for large images")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("dither"),
+        )
+        .arg(stream_arg())
         .arg(
             Arg::new("verbose")
                 .short('v')
@@ -96,6 +274,278 @@ fn build_cli() -> Command {
                         .value_parser(value_parser!(Shell)),
                 ),
         )
+        .subcommand(extract_command())
+}
+
+fn extract_command() -> Command {
+    Command::new("extract")
+        .about("Extract a palette from an image, as JSON usable with --palette")
+        .args([
+            Arg::new("image")
+                .required(true)
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+            Arg::new("output")
+                .help("File to write the palette to, printed if not given")
+                .value_parser(value_parser!(PathBuf))
+                .value_hint(ValueHint::FilePath),
+            Arg::new("count")
+                .short('n')
+                .long("count")
+                .help("Number of colors to extract")
+                .value_parser(value_parser!(u16).range(1..))
+                .default_value("16"),
+            Arg::new("quantizer")
+                .short('q')
+                .long("quantizer")
+                .value_parser(value_parser!(CliQuantizer))
+                .default_value("k-means"),
+        ])
+}
+
+fn stream_arg() -> Arg {
+    Arg::new("stream")
+        .long("stream")
+        .help("Convert a PNG band by band while reading and writing it, for huge images")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all([
+            "dither",
+            "lut",
+            "despeckle",
+            "majority",
+            "mask",
+            "region",
+            "protect",
+        ])
+}
+
+fn metric_args() -> [Arg; 5] {
+    [
+        Arg::new("method")
+            .short('m')
+            .long("method")
+            .value_parser(value_parser!(CliDeltaMethods))
+            .default_value("de2000"),
+        Arg::new("cmc_l")
+            .long("cmc-l")
+            .help("Lightness weight of --method cmc")
+            .value_parser(value_parser!(f32))
+            .default_value("2.0"),
+        Arg::new("cmc_c")
+            .long("cmc-c")
+            .help("Chroma weight of --method cmc")
+            .value_parser(value_parser!(f32))
+            .default_value("1.0"),
+        Arg::new("space")
+            .long("space")
+            .help("Color space to match colors in, --method only applies to lab")
+            .value_parser(value_parser!(CliSpace))
+            .default_value("lab"),
+        Arg::new("weights")
+            .long("weights")
+            .value_name("L,A,B")
+            .help("Weights of the lightness, a and b differences, e.g. 0.5,1,1 to favor hue, a weight of 0 ignores a channel but is slower for large palettes")
+            .value_parser(parse_weights),
+    ]
+}
+
+fn alpha_args() -> [Arg; 4] {
+    [
+        Arg::new("alpha_threshold")
+            .long("alpha-threshold")
+            .help("Make pixels with a lower alpha, from 0 to 255, fully transparent")
+            .value_parser(value_parser!(u8))
+            .default_value("0"),
+        Arg::new("premultiply")
+            .long("premultiply")
+            .help("Match colors premultiplied by their alpha")
+            .action(ArgAction::SetTrue),
+        Arg::new("alpha_levels")
+            .long("alpha-levels")
+            .help("Quantize alpha to this many evenly spaced levels")
+            .value_parser(value_parser!(u8).range(2..)),
+        Arg::new("transparent")
+            .long("transparent")
+            .help("Map fully transparent pixels to a single extra palette entry")
+            .action(ArgAction::SetTrue),
+    ]
+}
+
+fn despeckle_args() -> [Arg; 2] {
+    [
+        Arg::new("despeckle")
+            .long("despeckle")
+            .value_name("SIZE")
+            .help("Merge areas of fewer than SIZE pixels of one palette color into their surroundings")
+            .value_parser(value_parser!(usize)),
+        Arg::new("majority")
+            .long("majority")
+            .value_name("RADIUS")
+            .help("Give every pixel the most common palette color within RADIUS, before --despeckle")
+            .value_parser(value_parser!(usize)),
+    ]
+}
+
+fn region_args() -> [Arg; 2] {
+    [
+        Arg::new("region_size")
+            .long("region-size")
+            .help("Approximate width and height in pixels of the regions of --mapping regions")
+            .value_parser(value_parser!(u32).range(1..))
+            .default_value("16"),
+        Arg::new("compactness")
+            .long("compactness")
+            .help("How regular the regions of --mapping regions are, lower values follow edges more closely")
+            .value_parser(value_parser!(f32))
+            .default_value("10.0"),
+    ]
+}
+
+fn mask_args() -> [Arg; 2] {
+    [
+        Arg::new("mask")
+            .long("mask")
+            .help("Only convert where this grayscale image is white, fading out where it is gray")
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath),
+        Arg::new("region")
+            .long("region")
+            .value_name("SHAPE")
+            .help("Only convert inside rect:X,Y,WIDTH,HEIGHT or polygon:X1,Y1,X2,Y2,X3,Y3,..., can be repeated")
+            .value_parser(parse_region)
+            .action(ArgAction::Append),
+    ]
+}
+
+fn protect_args() -> [Arg; 3] {
+    [
+        Arg::new("protect")
+            .long("protect")
+            .value_name("COLOR")
+            .help("Keep pixels of this hex color as they are, can be repeated")
+            .value_parser(parse_hex_color)
+            .action(ArgAction::Append),
+        Arg::new("protect_tolerance")
+            .long("protect-tolerance")
+            .value_name("DELTA_E")
+            .help("How far pixels can be from a --protect color to be kept, as ΔE2000")
+            .value_parser(value_parser!(f32))
+            .default_value("2.0"),
+        Arg::new("overrides")
+            .long("overrides")
+            .help(r##"A JSON file mapping hex colors to palette color names, like {"#ff0000": "red"}, which always become that color"##)
+            .value_parser(value_parser!(PathBuf))
+            .value_hint(ValueHint::FilePath)
+            .conflicts_with("dither"),
+    ]
+}
+
+fn parse_hex_color(value: &str) -> Result<Lab, String> {
+    let hex = value.trim_start_matches('#');
+    let color = u32::from_str_radix(hex, 16)
+        .ok()
+        .filter(|_| hex.len() == 6)
+        .ok_or("expected a hex color like #ff8800")?;
+    let [_, r, g, b] = color.to_be_bytes();
+    Ok(Lab::from_rgb(&[r, g, b]))
+}
+
+fn parse_region(value: &str) -> Result<Region, String> {
+    let (shape, numbers) = value
+        .split_once(':')
+        .ok_or("expected rect:X,Y,WIDTH,HEIGHT or polygon:X1,Y1,X2,Y2,...")?;
+    match shape {
+        "rect" => {
+            let numbers = numbers
+                .split(',')
+                .map(|number| number.trim().parse::<u32>().map_err(|e| format!("{e}")))
+                .collect::<Result<Vec<_>, _>>()?;
+            match numbers[..] {
+                [x, y, width, height] => Ok(Region::Rectangle {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => Err("expected four numbers for rect:X,Y,WIDTH,HEIGHT".to_string()),
+            }
+        }
+        "polygon" => {
+            let numbers = numbers
+                .split(',')
+                .map(|number| number.trim().parse::<f32>().map_err(|e| format!("{e}")))
+                .collect::<Result<Vec<_>, _>>()?;
+            if numbers.len() < 6 || numbers.len() % 2 != 0 {
+                return Err("expected at least three X,Y points for polygon".to_string());
+            }
+            Ok(Region::Polygon(
+                numbers
+                    .chunks_exact(2)
+                    .map(|point| (point[0], point[1]))
+                    .collect(),
+            ))
+        }
+        _ => Err(format!("unknown shape {shape}, expected rect or polygon")),
+    }
+}
+
+fn parse_strength(value: &str) -> Result<f32, String> {
+    let strength: f32 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&strength) {
+        Ok(strength)
+    } else {
+        Err("must be between 0.0 and 1.0".to_string())
+    }
+}
+
+/// Accepts a built-in palette or a palette file, offering the built-in palettes
+/// as possible values for help and shell completions.
+#[derive(Clone)]
+struct PaletteParser;
+
+impl TypedValueParser for PaletteParser {
+    type Value = String;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        parse_palette.parse_ref(cmd, arg, value)
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        let mut palettes = LIBRARY.keys().map(String::as_str).collect::<Vec<_>>();
+        palettes.sort_unstable();
+        Some(Box::new(palettes.into_iter().map(PossibleValue::new)))
+    }
+}
+
+fn parse_palette(value: &str) -> Result<String, String> {
+    if LIBRARY.contains_key(value) || Path::new(value).is_file() {
+        Ok(value.to_string())
+    } else {
+        let mut palettes = LIBRARY.keys().cloned().collect::<Vec<_>>();
+        palettes.sort();
+        Err(format!(
+            "not a palette file or one of: {}",
+            palettes.join(", ")
+        ))
+    }
+}
+
+fn parse_weights(value: &str) -> Result<[f32; 3], String> {
+    let weights = value
+        .split(',')
+        .map(|weight| weight.trim().parse::<f32>().map_err(|e| format!("{e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    match <[f32; 3]>::try_from(weights) {
+        Ok([0.0, 0.0, 0.0]) => Err("at least one weight has to be above 0".to_string()),
+        Ok(weights) if weights.iter().all(|weight| *weight >= 0.0) => Ok(weights),
+        Ok(_) => Err("weights can't be negative".to_string()),
+        Err(_) => Err("expected three comma separated weights".to_string()),
+    }
 }
 
 fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
@@ -107,6 +557,13 @@ fn slugify(s: &str) -> String {
 }
 
 fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
     let matches = build_cli().get_matches();
 
     if let Some(completion) = matches.subcommand_matches("completion") {
@@ -116,95 +573,761 @@ fn main() {
         print_completions(shell, &mut cmd);
         std::process::exit(0);
     }
+    if let Some(extract) = matches.subcommand_matches("extract") {
+        return extract_palette(extract);
+    }
 
     let input = matches.get_one::<PathBuf>("input").expect("required");
-    let method: DEMethod = (*matches
-        .get_one::<CliDeltaMethods>("method")
-        .expect("default"))
-    .into();
+    let metric: Metric = match *matches.get_one::<CliSpace>("space").expect("default") {
+        CliSpace::Lab => matches
+            .get_one::<CliDeltaMethods>("method")
+            .expect("default")
+            .metric(
+                *matches.get_one::<f32>("cmc_l").expect("default"),
+                *matches.get_one::<f32>("cmc_c").expect("default"),
+            ),
+        // other spaces always use the euclidean distance, so an explicit method would be ignored
+        _ if matches.value_source("method") == Some(ValueSource::CommandLine) => {
+            return Err("--method only applies to --space lab".into());
+        }
+        space => Metric::Euclidean(space.into()),
+    };
+    let weighted;
+    let method: &dyn ColorDistance = match matches.get_one::<[f32; 3]>("weights") {
+        Some(weights) => {
+            weighted = Weighted::new(metric, *weights);
+            &weighted
+        }
+        None => &metric,
+    };
     let palette = matches.get_one::<String>("palette").expect("default");
     let flavour = matches.get_one::<String>("flavour");
 
     let file_path = Path::new(input);
     println!("Reading image from {}", file_path.display());
-    let file_ext = file_path.extension().unwrap().to_str().unwrap();
+    let file_ext = file_path
+        .extension()
+        .and_then(OsStr::to_str)
+        .ok_or("the input needs a file extension to tell its format")?;
 
-    let mut custom_colorscheme: ColorScheme = ColorScheme::new();
-    let colorscheme = LIBRARY.get(palette).unwrap_or_else(|| {
-        let contents = read_to_string(palette).expect("something went wrong reading the file");
+    let custom_colorscheme: ColorScheme;
+    let colorscheme = if let Some(colorscheme) = LIBRARY.get(palette) {
+        colorscheme
+    } else {
+        let contents = read_to_string(palette)?;
 
-        custom_colorscheme = parse_colorscheme(serde_json::from_str(&contents).unwrap());
+        custom_colorscheme = parse_colorscheme(serde_json::from_str(&contents)?)?;
         &custom_colorscheme
-    });
+    };
 
     let output = matches.get_one::<String>("output").map_or_else(
         || {
-            let input = input.file_stem().unwrap().to_str().unwrap();
+            let input = input.file_stem().unwrap_or_default().to_string_lossy();
             let flavour = flavour.map_or_else(String::new, |flavour| slugify(flavour));
-            let palette = slugify(palette);
+            let palette = slugify(&Path::new(palette).file_stem().unwrap().to_string_lossy());
             format!("{input}{palette}{flavour}.{file_ext}")
         },
         Clone::clone,
     );
 
-    let labs: Vec<Lab> = flavour.map_or_else(
-        || {
-            let palette: Palette = colorscheme
-                .values()
-                .next()
-                .expect("palette should have a flavour")
-                .clone();
-            get_labs(palette)
-        },
-        |flavour| {
-            let mut labs: Vec<Lab> = vec![];
-            if colorscheme.contains_key(flavour) {
-                labs.append(&mut get_labs(colorscheme.get(flavour).unwrap().clone()));
-            } else {
-                eprintln!("Could not find flavour: {flavour}");
-                eprintln!(
-                    "Available flavours: {}",
-                    colorscheme
-                        .keys()
-                        .map(|s| s.to_lowercase())
-                        .collect::<Vec<_>>()
-                        .join(", ")
-                );
-                std::process::exit(1);
-            }
-            labs
-        },
-    );
+    let palette = select_flavour(colorscheme, flavour);
+    let overrides = match matches.get_one::<PathBuf>("overrides") {
+        Some(path) => {
+            let json = serde_json::from_str(&read_to_string(path)?)?;
+            Overrides::new(parse_overrides(&json, &palette)?)
+        }
+        None => Overrides::default(),
+    };
+
+    let labs = get_labs(palette);
+    let labs = match matches.get_one::<usize>("expand") {
+        Some(&shades) => {
+            let space = *matches
+                .get_one::<CliRampSpace>("expand_space")
+                .expect("default");
+            faerber_lib::ramp::expand_palette(&labs, shades, space.into())
+        }
+        None => labs,
+    };
+    // palette indices are 16-bit, and the transparent entry takes the index after the last color
+    if labs.len() > usize::from(u16::MAX) {
+        return Err(format!(
+            "the palette has {} colors, at most {} are supported, use fewer --expand shades",
+            labs.len(),
+            u16::MAX
+        )
+        .into());
+    }
 
     if file_ext == "svg" {
-        let contents = read_to_string(input).unwrap();
-        let result = faerber_lib::convert_vector(&contents, method, &labs);
+        let contents = read_to_string(input)?;
+        let options = VectorOptions {
+            overrides,
+            protected: read_protected(&matches),
+        };
+        let result = faerber_lib::convert_vector_with(&contents, method, &labs, &options)?;
         println!("{result}");
-        let mut fp = File::create(output).unwrap();
-        fp.write_all(result.as_bytes()).unwrap();
+        let mut fp = File::create(output)?;
+        fp.write_all(result.as_bytes())?;
+        Ok(())
+    } else {
+        convert_raster(&matches, input, &output, method, &labs, &overrides)
+    }
+}
+
+fn extract_palette(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let image = matches.get_one::<PathBuf>("image").expect("required");
+    let count = *matches.get_one::<u16>("count").expect("default");
+    let quantizer = *matches
+        .get_one::<CliQuantizer>("quantizer")
+        .expect("default");
+
+    let img = image::open(image)?.into_rgba8();
+    let labs = faerber_lib::extract::extract(&img, count.into(), quantizer.into());
+
+    // zero padded names keep the most used colors first, as palettes are sorted by name
+    let width = labs.len().to_string().len().max(2);
+    let palette: serde_json::Map<String, serde_json::Value> = labs
+        .iter()
+        .enumerate()
+        .map(|(i, lab)| {
+            let [r, g, b] = lab.to_rgb();
+            (
+                format!("color{:0width$}", i + 1),
+                format!("#{r:02x}{g:02x}{b:02x}").into(),
+            )
+        })
+        .collect();
+    let name = image.file_stem().unwrap().to_string_lossy().to_string();
+    let mut scheme = serde_json::Map::new();
+    scheme.insert(name, palette.into());
+    let json = serde_json::to_string_pretty(&scheme)?;
+
+    match matches.get_one::<PathBuf>("output") {
+        Some(output) => std::fs::write(output, json + "\n")?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+/// Returns the flavour of the color scheme, or its first one if none is given,
+/// exiting if the color scheme doesn't have the flavour.
+fn select_flavour(colorscheme: &ColorScheme, flavour: Option<&String>) -> Palette {
+    let Some(flavour) = flavour else {
+        return colorscheme
+            .values()
+            .next()
+            .expect("color schemes should have a flavour")
+            .clone();
+    };
+    if let Some(palette) = colorscheme.get(flavour) {
+        return palette.clone();
+    }
+    eprintln!("Could not find flavour: {flavour}");
+    eprintln!(
+        "Available flavours: {}",
+        colorscheme
+            .keys()
+            .map(|s| s.to_lowercase())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    std::process::exit(1);
+}
+
+fn convert_raster(
+    matches: &ArgMatches,
+    input: &Path,
+    output: &str,
+    method: &dyn ColorDistance,
+    labs: &[Lab],
+    overrides: &Overrides,
+) -> Result<(), Box<dyn Error>> {
+    let dither = *matches.get_one::<CliDither>("dither").expect("default");
+    let mapping = *matches.get_one::<CliMapping>("mapping").expect("default");
+    let strength = *matches.get_one::<f32>("strength").expect("default");
+
+    let alpha = AlphaOptions {
+        threshold: *matches.get_one::<u8>("alpha_threshold").expect("default"),
+        premultiply: matches.get_flag("premultiply"),
+        levels: matches.get_one::<u8>("alpha_levels").copied(),
+        transparent: matches.get_flag("transparent"),
+    };
+
+    if matches.get_flag("stream") {
+        if mapping != CliMapping::Nearest || strength < 1.0 || alpha != AlphaOptions::default() {
+            return Err("--stream only supports the nearest mapping, without alpha options".into());
+        }
+        return convert_stream(input, output, method, labs, overrides);
+    }
+
+    let (input_image, profile) = read_image(input)?;
+    let color = input_image.color();
+    let deep = color.bytes_per_pixel() > color.channel_count();
+    let mask = read_mask(matches, input_image.width(), input_image.height())?;
+    let protected = read_protected(matches);
+    let recolor = Recolor {
+        mapping,
+        slic: Slic {
+            region_size: *matches.get_one::<u32>("region_size").expect("default"),
+            compactness: *matches.get_one::<f32>("compactness").expect("default"),
+            ..Slic::default()
+        },
+        strength,
+        mask: mask.as_ref(),
+        protected: &protected,
+        overrides,
+    };
+    let despeckle = matches.get_one::<usize>("despeckle").copied();
+    let majority = matches.get_one::<usize>("majority").copied();
+    if deep || profile.is_some() {
+        if dither == CliDither::None
+            && !matches.get_flag("lut")
+            && alpha == AlphaOptions::default()
+            && despeckle.is_none()
+            && majority.is_none()
+        {
+            let converted = convert_deep(input_image, profile.as_ref(), &recolor, method, labs);
+            let png = encode_png(&converted, deep, profile.as_ref())?;
+            let compressed = oxipng::optimize_from_memory(&png, &oxipng::Options::default())?;
+            return Ok(std::fs::write(output, compressed)?);
+        }
+        eprintln!(
+            "Dithering, --lut, despeckling and the alpha options only support 8-bit sRGB, converting the input"
+        );
+    }
+    let original: RgbaImage = match &profile {
+        Some(profile) => {
+            let mut img = input_image.into_rgba32f();
+            profile.to_srgb(&mut img);
+            DynamicImage::ImageRgba32F(img).into_rgba8()
+        }
+        None => input_image.to_rgba8(),
+    };
+    let img = alpha.prepare(&original);
+
+    let mut result = match_pixels(matches, &img, &recolor, method, labs)?;
+
+    if mapping == CliMapping::PreserveLightness {
+        result.pixels = faerber_lib::preserve_lightness(&original, &result.pixels);
+    }
+    if strength < 1.0 {
+        result.pixels = faerber_lib::blend(&original, &result.pixels, strength);
+    }
+    alpha.finish(&img, &mut result, labs.len());
+    if let Some(mask) = &mask {
+        result.pixels = apply_mask(&original, &result.pixels, mask);
+    }
+    let restored = protected.restore(&original, &mut result.pixels);
+
+    // mixed, blended, masked or protected colors are no longer palette colors,
+    // so they can't be written as indices
+    let palette = (matches!(mapping, CliMapping::Nearest | CliMapping::Regions)
+        && strength >= 1.0
+        && mask.is_none()
+        && restored == 0)
+        .then_some(labs);
+    write_converted(&result, palette, img.width(), img.height(), output)
+}
+
+/// Matches the pixels of an 8-bit image to the palette, with the steps that need
+/// 8-bit images: dithering, --lut and despeckling.
+fn match_pixels(
+    matches: &ArgMatches,
+    img: &RgbaImage,
+    recolor: &Recolor,
+    method: &dyn ColorDistance,
+    labs: &[Lab],
+) -> Result<Indexed, Box<dyn Error>> {
+    let dither = *matches.get_one::<CliDither>("dither").expect("default");
+    let dither_strength = *matches.get_one::<f32>("dither_strength").expect("default");
+    let despeckle = matches.get_one::<usize>("despeckle").copied();
+    let majority = matches.get_one::<usize>("majority").copied();
+
+    let mut result = if recolor.mapping == CliMapping::Soft {
+        if dither != CliDither::None || matches.get_flag("lut") {
+            return Err("soft mapping can't be combined with dithering or --lut".into());
+        }
+        if despeckle.is_some() || majority.is_some() {
+            return Err("soft mapping can't be combined with despeckling".into());
+        }
+        Indexed {
+            indices: vec![],
+            pixels: faerber_lib::convert_soft_with_overrides(img, method, labs, recolor.overrides),
+        }
+    } else if recolor.mapping == CliMapping::Regions {
+        if dither != CliDither::None || matches.get_flag("lut") {
+            return Err("region mapping can't be combined with dithering or --lut".into());
+        }
+        recolor
+            .slic
+            .convert_with_overrides(img, method, labs, recolor.overrides)
+    } else if matches.get_flag("lut") {
+        Lut::with_overrides(method, labs, recolor.overrides).convert_indexed(img)
     } else {
-        let img: RgbaImage = match image::open(input) {
-            Ok(img) => img.to_rgba8(),
-            Err(e) => {
-                eprintln!("Could not open image: {e}");
-                std::process::exit(1);
+        dither.convert(
+            img,
+            method,
+            labs,
+            dither_strength,
+            recolor.protected,
+            recolor.overrides,
+        )
+    };
+
+    let width = img.width() as usize;
+    if let Some(radius) = majority {
+        faerber_lib::despeckle::majority(&mut result, width, radius);
+    }
+    if let Some(size) = despeckle {
+        faerber_lib::despeckle::remove_islands(&mut result, width, size);
+    }
+    Ok(result)
+}
+
+fn read_protected(matches: &ArgMatches) -> ProtectedColors {
+    ProtectedColors::new(
+        matches
+            .get_many::<Lab>("protect")
+            .map_or_else(Vec::new, |colors| colors.copied().collect()),
+        *matches
+            .get_one::<f32>("protect_tolerance")
+            .expect("default"),
+    )
+}
+
+/// Builds the mask from --mask and every --region, or returns `None` if neither is given.
+fn read_mask(
+    matches: &ArgMatches,
+    width: u32,
+    height: u32,
+) -> Result<Option<GrayImage>, Box<dyn Error>> {
+    let regions = matches.get_many::<Region>("region");
+    let mut mask = match matches.get_one::<PathBuf>("mask") {
+        Some(path) => {
+            let mask = image::open(path)?.into_luma8();
+            if mask.dimensions() != (width, height) {
+                return Err(format!(
+                    "the mask is {}x{}, but the image is {width}x{height}",
+                    mask.width(),
+                    mask.height()
+                )
+                .into());
             }
-        };
+            mask
+        }
+        None if regions.is_some() => GrayImage::new(width, height),
+        None => return Ok(None),
+    };
+    for region in regions.into_iter().flatten() {
+        region.draw(&mut mask);
+    }
+    Ok(Some(mask))
+}
+
+/// Writes the converted pixels as a PNG, indexed if a palette is given and it fits.
+fn write_converted(
+    result: &Indexed,
+    palette: Option<&[Lab]>,
+    width: u32,
+    height: u32,
+    output: &str,
+) -> Result<(), Box<dyn Error>> {
+    let indexed = match palette {
+        Some(labs) => encode_indexed(result, labs, width, height)?,
+        None => None,
+    };
 
-        let result = faerber_lib::convert(&img, method, &labs);
+    let (png, options) = if let Some(png) = indexed {
+        // reordering or dropping palette entries would break swapping the palette later
+        let options = oxipng::Options {
+            palette_reduction: false,
+            color_type_reduction: false,
+            grayscale_reduction: false,
+            ..Default::default()
+        };
+        (png, options)
+    } else {
         let mut c = Cursor::new(Vec::new());
         image::write_buffer_with_format(
             &mut c,
-            &result,
-            img.width(),
-            img.height(),
+            &result.pixels,
+            width,
+            height,
             image::ColorType::Rgba8,
             image::ImageFormat::Png,
         )
-        .expect("Could not write to buffer");
-        let compressed = oxipng::optimize_from_memory(&c.into_inner(), &oxipng::Options::default());
-        let mut file = std::fs::File::create(output).expect("Could not create file");
-        file.write_all(&compressed.expect("Could not compress file"))
-            .expect("Could not write to file");
+        .map_err(FaerberError::from)?;
+        (c.into_inner(), oxipng::Options::default())
+    };
+    let compressed = oxipng::optimize_from_memory(&png, &options)?;
+    let mut file = std::fs::File::create(output)?;
+    file.write_all(&compressed)?;
+    Ok(())
+}
+
+/// Converts a PNG band by band while it is decoded and encoded, so that memory use
+/// doesn't grow with the size of the image. The output is RGBA with the input's bit depth.
+fn convert_stream(
+    input: &Path,
+    output: &str,
+    method: &dyn ColorDistance,
+    labs: &[Lab],
+    overrides: &Overrides,
+) -> Result<(), Box<dyn Error>> {
+    const BAND_ROWS: usize = 256;
+
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(input)?));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    if reader.info().interlaced {
+        return Err("interlaced PNGs can't be converted band by band".into());
+    }
+    if reader.info().icc_profile.is_some() {
+        eprintln!("--stream ignores the ICC profile and treats the image as sRGB");
+    }
+    let (width, height) = reader.info().size();
+    let (color_type, bit_depth) = reader.output_color_type();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(output)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(bit_depth);
+    let mut writer = encoder.write_header()?.into_stream_writer()?;
+
+    let converter = BandConverter::with_overrides(method, labs, overrides);
+    let mut band = Vec::with_capacity(reader.output_line_size(width) * BAND_ROWS);
+    let mut rows = 0;
+    while let Some(row) = reader.next_row()? {
+        band.extend_from_slice(row.data());
+        rows += 1;
+        if rows == BAND_ROWS {
+            writer.write_all(&convert_band(&converter, &band, color_type, bit_depth))?;
+            band.clear();
+            rows = 0;
+        }
+    }
+    writer.write_all(&convert_band(&converter, &band, color_type, bit_depth))?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Converts a band of decoded PNG rows, returning it as RGBA rows of the same bit depth.
+fn convert_band(
+    converter: &BandConverter,
+    band: &[u8],
+    color_type: png::ColorType,
+    bit_depth: png::BitDepth,
+) -> Vec<u8> {
+    if bit_depth == png::BitDepth::Sixteen {
+        let samples: Vec<u16> = band
+            .chunks_exact(2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect();
+        converter
+            .convert(&expand_to_rgba(&samples, color_type, u16::MAX))
+            .iter()
+            .flat_map(|channel| channel.to_be_bytes())
+            .collect()
+    } else {
+        converter.convert(&expand_to_rgba(band, color_type, u8::MAX))
+    }
+}
+
+/// Turns grayscale, grayscale with alpha or RGB samples into RGBA.
+fn expand_to_rgba<T: Copy>(samples: &[T], color_type: png::ColorType, opaque: T) -> Vec<T> {
+    match color_type {
+        png::ColorType::Grayscale => samples
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, opaque])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => samples
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        png::ColorType::Rgb => samples
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], opaque])
+            .collect(),
+        // palette images are expanded to RGB or RGBA while decoding
+        png::ColorType::Rgba | png::ColorType::Indexed => samples.to_vec(),
+    }
+}
+
+/// Decodes the image along with its ICC profile, which is left out if it describes sRGB.
+fn read_image(input: &Path) -> Result<(DynamicImage, Option<IccProfile>), Box<dyn Error>> {
+    let reader = image::io::Reader::open(input)?.with_guessed_format()?;
+    let (img, icc) = match reader.format() {
+        Some(ImageFormat::Png) => {
+            let mut decoder =
+                PngDecoder::new(BufReader::new(File::open(input)?)).map_err(FaerberError::from)?;
+            let icc = decoder.icc_profile();
+            (DynamicImage::from_decoder(decoder), icc)
+        }
+        Some(ImageFormat::Jpeg) => {
+            let mut decoder =
+                JpegDecoder::new(BufReader::new(File::open(input)?)).map_err(FaerberError::from)?;
+            let icc = decoder.icc_profile();
+            (DynamicImage::from_decoder(decoder), icc)
+        }
+        _ => (reader.decode(), None),
+    };
+    let img = img.map_err(FaerberError::from)?;
+
+    let profile = icc.and_then(|icc| match IccProfile::parse(&icc) {
+        Ok(profile) => (!profile.is_srgb()).then_some(profile),
+        Err(e) => {
+            eprintln!("Treating the image as sRGB: {e}");
+            None
+        }
+    });
+    Ok((img, profile))
+}
+
+/// Converts a 16-bit, floating-point or ICC tagged image at its own precision,
+/// matching colors in sRGB and moving them back into the profile afterwards.
+fn convert_deep(
+    img: DynamicImage,
+    profile: Option<&IccProfile>,
+    recolor: &Recolor,
+    method: &dyn ColorDistance,
+    labs: &[Lab],
+) -> DynamicImage {
+    let (width, height) = (img.width(), img.height());
+    let same_size = "converted image should have the same size";
+    if profile.is_none()
+        && !matches!(
+            img,
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+        )
+    {
+        let pixels = recolor.apply(&img.into_rgba16(), method, labs);
+        return DynamicImage::ImageRgba16(
+            ImageBuffer::from_raw(width, height, pixels).expect(same_size),
+        );
+    }
+
+    let mut img = img.into_rgba32f();
+    if let Some(profile) = profile {
+        profile.to_srgb(&mut img);
+    }
+    let mut pixels = recolor.apply(&img, method, labs);
+    if let Some(profile) = profile {
+        profile.from_srgb(&mut pixels);
+    }
+    DynamicImage::ImageRgba32F(ImageBuffer::from_raw(width, height, pixels).expect(same_size))
+}
+
+/// Encodes an RGBA PNG with 8 or 16 bits per channel, embedding the ICC profile if given.
+fn encode_png(
+    img: &DynamicImage,
+    sixteen_bit: bool,
+    profile: Option<&IccProfile>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, img.width(), img.height());
+    encoder.set_color(png::ColorType::Rgba);
+    let data = if sixteen_bit {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        img.to_rgba16()
+            .iter()
+            .flat_map(|channel| channel.to_be_bytes())
+            .collect()
+    } else {
+        encoder.set_depth(png::BitDepth::Eight);
+        img.to_rgba8().into_raw()
+    };
+    let mut writer = encoder.write_header()?;
+    if let Some(profile) = profile {
+        writer.write_chunk(png::chunk::iCCP, &iccp_chunk(profile.data()))?;
+    }
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(png)
+}
+
+/// The contents of an iCCP chunk, with the profile wrapped in uncompressed deflate blocks.
+fn iccp_chunk(profile: &[u8]) -> Vec<u8> {
+    // profile name, null separator and compression method
+    let mut chunk = b"icc\0\0".to_vec();
+    // zlib header for deflate with a 32 KiB window and no preset dictionary
+    chunk.extend_from_slice(&[0x78, 0x01]);
+    let blocks = profile.chunks(usize::from(u16::MAX));
+    let count = blocks.len();
+    for (i, block) in blocks.enumerate() {
+        let length = u16::try_from(block.len()).expect("blocks should fit into 64 KiB");
+        chunk.push(u8::from(i + 1 == count));
+        chunk.extend_from_slice(&length.to_le_bytes());
+        chunk.extend_from_slice(&(!length).to_le_bytes());
+        chunk.extend_from_slice(block);
+    }
+    if profile.is_empty() {
+        chunk.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in profile {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    chunk.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    chunk
+}
+
+/// How the colors of an image are mapped, for the steps that work at any precision.
+struct Recolor<'a> {
+    mapping: CliMapping,
+    slic: Slic,
+    strength: f32,
+    mask: Option<&'a GrayImage>,
+    protected: &'a ProtectedColors,
+    overrides: &'a Overrides,
+}
+
+impl Recolor<'_> {
+    /// Maps the colors of an image of any precision, without the steps that need 8-bit images.
+    fn apply<P: LabPixel>(
+        &self,
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+        method: &dyn ColorDistance,
+        labs: &[Lab],
+    ) -> Vec<P::Subpixel> {
+        let mut pixels = match self.mapping {
+            CliMapping::Soft => {
+                faerber_lib::convert_soft_with_overrides(img, method, labs, self.overrides)
+            }
+            CliMapping::Regions => {
+                self.slic
+                    .convert_with_overrides(img, method, labs, self.overrides)
+                    .pixels
+            }
+            CliMapping::Nearest | CliMapping::PreserveLightness => {
+                faerber_lib::convert_with_overrides(img, method, labs, self.overrides)
+            }
+        };
+        if self.mapping == CliMapping::PreserveLightness {
+            pixels = faerber_lib::preserve_lightness(img, &pixels);
+        }
+        if self.strength < 1.0 {
+            pixels = faerber_lib::blend(img, &pixels, self.strength);
+        }
+        if let Some(mask) = self.mask {
+            pixels = apply_mask(img, &pixels, mask);
+        }
+        self.protected.restore(img, &mut pixels);
+        pixels
+    }
+}
+
+/// Encodes the converted image as an indexed PNG, with the palette colors in order at the
+/// start of the PLTE chunk and an extra entry for every partially transparent palette color.
+/// Returns `None` if the image needs more than 256 palette entries.
+fn encode_indexed(
+    result: &Indexed,
+    labs: &[Lab],
+    width: u32,
+    height: u32,
+) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    if labs.is_empty() || labs.len() > 256 {
+        return Ok(None);
+    }
+
+    let mut plte: Vec<u8> = labs.iter().flat_map(|lab| lab.to_rgb()).collect();
+    let mut trns: Vec<u8> = vec![];
+    let mut entries: HashMap<(u16, u8), u8> = HashMap::new();
+    let mut data = Vec::with_capacity(result.indices.len());
+    for (&index, pixel) in result.indices.iter().zip(result.pixels.chunks_exact(4)) {
+        let alpha = pixel[3];
+        if alpha == 255 {
+            data.push(u8::try_from(index)?);
+            continue;
+        }
+        let entry = if let Some(&entry) = entries.get(&(index, alpha)) {
+            entry
+        } else {
+            let Ok(entry) = u8::try_from(plte.len() / 3) else {
+                return Ok(None);
+            };
+            plte.extend_from_slice(&pixel[..3]);
+            trns.push(alpha);
+            entries.insert((index, alpha), entry);
+            entry
+        };
+        data.push(entry);
+    }
+    if !trns.is_empty() {
+        trns.splice(0..0, vec![255; labs.len()]);
+    }
+
+    let mut png = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(plte);
+    if !trns.is_empty() {
+        encoder.set_trns(trns);
+    }
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(Some(png))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read_image;
+    use std::fs::File;
+
+    /// A Display P3 matrix/TRC profile, with a gamma of 2.2 for every channel.
+    fn display_p3_profile() -> Vec<u8> {
+        const COLORANTS: [(&[u8; 4], [i32; 3]); 3] = [
+            (b"rXYZ", [33_758, 15_807, -72]),
+            (b"gXYZ", [19_137, 45_364, 2_746]),
+            (b"bXYZ", [10_296, 4_365, 51_387]),
+        ];
+        const CURVE_OFFSET: u32 = 132 + 6 * 12 + 3 * 20;
+
+        let mut profile = vec![0; 128];
+        profile[16..20].copy_from_slice(b"RGB ");
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend_from_slice(&6u32.to_be_bytes());
+        for (offset, (signature, _)) in (132 + 6 * 12..).step_by(20).zip(COLORANTS) {
+            profile.extend_from_slice(signature);
+            profile.extend_from_slice(&u32::to_be_bytes(offset));
+            profile.extend_from_slice(&20u32.to_be_bytes());
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"] {
+            profile.extend_from_slice(signature);
+            profile.extend_from_slice(&CURVE_OFFSET.to_be_bytes());
+            profile.extend_from_slice(&14u32.to_be_bytes());
+        }
+        for (_, xyz) in COLORANTS {
+            profile.extend_from_slice(b"XYZ \0\0\0\0");
+            profile.extend(xyz.into_iter().flat_map(i32::to_be_bytes));
+        }
+        // a single entry is a gamma as unsigned 8.8 fixed point
+        profile.extend_from_slice(b"curv\0\0\0\0");
+        profile.extend_from_slice(&1u32.to_be_bytes());
+        profile.extend_from_slice(&563u16.to_be_bytes());
+        profile
+    }
+
+    #[test]
+    fn reads_compressed_iccp_profiles() {
+        let profile = display_p3_profile();
+        let mut info = png::Info::with_size(1, 1);
+        info.color_type = png::ColorType::Rgba;
+        info.bit_depth = png::BitDepth::Eight;
+        info.icc_profile = Some(profile.clone().into());
+
+        let path = std::env::temp_dir().join("faerber-iccp-test.png");
+        let encoder = png::Encoder::with_info(File::create(&path).unwrap(), info).unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[255, 0, 0, 255]).unwrap();
+        writer.finish().unwrap();
+        let (_, icc) = read_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let icc = icc.expect("a Display P3 profile should be read");
+        assert_eq!(icc.data(), profile);
     }
 }
@@ -1,7 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 
 use deltae::DEMethod;
-use faerber_lib::{convert, convert_color, rgba_pixels_to_labs};
+use faerber_lib::{convert, convert_color, rgba_pixels_to_labs, ColorSpace, KdTree, Lut, Metric};
 use image::RgbaImage;
 
 pub fn benchmark(c: &mut Criterion) {
@@ -53,6 +53,61 @@ pub fn benchmark(c: &mut Criterion) {
             b.iter(|| convert(&img, DEMethod::DE2000, &palette))
         });
 
+    let metrics = [
+        ("cmc", Metric::DeltaE(DEMethod::DECMC(2.0, 1.0))),
+        ("din99o", Metric::Euclidean(ColorSpace::Din99o)),
+        ("oklab", Metric::Euclidean(ColorSpace::Oklab)),
+        ("cam16ucs", Metric::Euclidean(ColorSpace::Cam16Ucs)),
+        ("jzazbz", Metric::Euclidean(ColorSpace::Jzazbz)),
+        ("hyab", Metric::HyAB),
+    ];
+    let mut group = c.benchmark_group("metrics");
+    group.sample_size(10);
+    for (name, metric) in metrics {
+        group.bench_function(format!("pixel_{name}"), |b| {
+            b.iter(|| convert_color(metric, &palette, &random_lab))
+        });
+        group.bench_function(format!("image_{name}"), |b| {
+            b.iter(|| convert(&img, metric, &palette))
+        });
+    }
+    group.finish();
+
+    // benchmark colorscheme: xterm-256, to compare a linear search with the k-d tree
+    let xterm: Vec<u32> = (0..216)
+        .map(|i| {
+            let level = |v: u32| if v == 0 { 0 } else { 55 + v * 40 };
+            (level(i / 36) << 16) | (level(i / 6 % 6) << 8) | level(i % 6)
+        })
+        .chain((0..24).map(|i| (8 + i * 10) * 0x01_01_01))
+        .collect();
+    let xterm_palette = faerber_lib::convert_palette_to_lab(&xterm);
+    let tree = KdTree::new(&xterm_palette);
+
+    c.benchmark_group("large palette")
+        .sample_size(100)
+        .bench_function("linear_de1976", |b| {
+            b.iter(|| convert_color(DEMethod::DE1976, &xterm_palette, &random_lab))
+        })
+        .bench_function("kdtree_de1976", |b| {
+            b.iter(|| tree.closest_index(DEMethod::DE1976, &random_lab))
+        })
+        .bench_function("linear_de1994g", |b| {
+            b.iter(|| convert_color(DEMethod::DE1994G, &xterm_palette, &random_lab))
+        })
+        .bench_function("kdtree_de1994g", |b| {
+            b.iter(|| tree.closest_index(DEMethod::DE1994G, &random_lab))
+        });
+
+    let lut = Lut::new(DEMethod::DE2000, &palette);
+
+    c.benchmark_group("lut")
+        .sample_size(10)
+        .bench_function("build_de2000", |b| {
+            b.iter(|| Lut::new(DEMethod::DE2000, &palette))
+        })
+        .bench_function("image_de2000", |b| b.iter(|| lut.convert(&img)));
+
     c.benchmark_group("other")
         .sample_size(10)
         .bench_function("rgba_pixels_to_lab", |b| {
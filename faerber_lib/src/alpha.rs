@@ -0,0 +1,89 @@
+use crate::Indexed;
+use image::RgbaImage;
+use rayon::prelude::*;
+
+/// How the alpha channel is handled when converting an image.
+///
+/// By default, pixels are matched on their color alone and keep their alpha.
+/// [`AlphaOptions::prepare`] changes the image before it is converted by any of the
+/// conversions in this crate, and [`AlphaOptions::finish`] fixes up their result.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AlphaOptions {
+    /// Pixels with a lower alpha become fully transparent.
+    pub threshold: u8,
+    /// Matches colors premultiplied by their alpha, so barely visible edge pixels
+    /// with arbitrary colors are matched as the dark colors they blend into.
+    pub premultiply: bool,
+    /// Quantizes the alpha of the converted pixels to this many evenly spaced levels,
+    /// `Some(2)` turns every pixel either opaque or transparent.
+    pub levels: Option<u8>,
+    /// Maps fully transparent pixels to an extra palette index after the palette colors,
+    /// with all channels set to 0, instead of keeping their matched color.
+    pub transparent: bool,
+}
+
+impl AlphaOptions {
+    /// Applies the threshold and premultiplication to a single pixel.
+    #[must_use]
+    pub fn prepare_color(&self, rgba: [u8; 4]) -> [u8; 4] {
+        let [r, g, b, alpha] = rgba;
+        if alpha < self.threshold {
+            return [0; 4];
+        }
+        if !self.premultiply {
+            return rgba;
+        }
+        let premultiply = |channel: u8| ((u16::from(channel) * u16::from(alpha) + 127) / 255) as u8;
+        [premultiply(r), premultiply(g), premultiply(b), alpha]
+    }
+
+    /// Quantizes `alpha` to the configured number of levels.
+    #[must_use]
+    pub fn quantize(&self, alpha: u8) -> u8 {
+        match self.levels {
+            Some(levels) if levels >= 2 => {
+                let step = 255.0 / f32::from(levels - 1);
+                ((f32::from(alpha) / step).round() * step).round() as u8
+            }
+            _ => alpha,
+        }
+    }
+
+    /// Returns the image to convert, with the threshold and premultiplication applied.
+    #[must_use]
+    pub fn prepare(&self, img: &RgbaImage) -> RgbaImage {
+        let mut prepared = img.clone();
+        if self.threshold > 0 || self.premultiply {
+            prepared.par_chunks_exact_mut(4).for_each(|pixel| {
+                pixel
+                    .copy_from_slice(&self.prepare_color([pixel[0], pixel[1], pixel[2], pixel[3]]));
+            });
+        }
+        prepared
+    }
+
+    /// Gives the converted pixels the alpha of the prepared image, quantized to the
+    /// configured levels, and moves fully transparent pixels to the transparent entry
+    /// at `palette_len` if enabled.
+    ///
+    /// The indices are left alone if `converted` has none, e.g. for mixed colors.
+    pub fn finish(&self, prepared: &RgbaImage, converted: &mut Indexed, palette_len: usize) {
+        let has_indices = !converted.indices.is_empty();
+        for (i, (pixel, original)) in converted
+            .pixels
+            .chunks_exact_mut(4)
+            .zip(prepared.as_raw().chunks_exact(4))
+            .enumerate()
+        {
+            let alpha = self.quantize(original[3]);
+            if alpha == 0 && self.transparent {
+                pixel.copy_from_slice(&[0; 4]);
+                if has_indices {
+                    converted.indices[i] = palette_len as u16;
+                }
+            } else {
+                pixel[3] = alpha;
+            }
+        }
+    }
+}
@@ -45,6 +45,12 @@ impl Lab {
         let rgb = LabBase::from(self).to_rgb();
         [rgb[0], rgb[1], rgb[2], (self.alpha * 255.0) as u8]
     }
+
+    /// The squared euclidean distance to `other` in CIELAB, ignoring alpha.
+    pub(crate) fn squared_distance(&self, other: &Self) -> f32 {
+        let (dl, da, db) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        dl.mul_add(dl, da.mul_add(da, db * db))
+    }
 }
 
 impl From<Lab> for LabValue {
@@ -0,0 +1,168 @@
+use crate::Indexed;
+use rayon::prelude::*;
+
+/// Replaces the index of every pixel with the most common index in the surrounding
+/// `(2 * radius + 1)²` square, if it's strictly more common than the pixel's own.
+///
+/// This is a mode filter: it removes specks up to about `radius` pixels wide,
+/// but also rounds off corners and thin lines. Only the color channels of changed
+/// pixels are replaced, so it should run before [`crate::AlphaOptions::finish`].
+/// Does nothing if `indexed` has no indices, e.g. for mixed colors.
+pub fn majority<T: Copy + Send + Sync>(indexed: &mut Indexed<T>, width: usize, radius: usize) {
+    if indexed.indices.is_empty() || width == 0 || radius == 0 {
+        return;
+    }
+    let height = indexed.indices.len() / width;
+    let indices = &indexed.indices;
+
+    let filtered: Vec<u16> = (0..indices.len())
+        .into_par_iter()
+        .map_init(Vec::new, |counts: &mut Vec<(u16, usize)>, i| {
+            let (x, y) = (i % width, i / width);
+            counts.clear();
+            for ny in y.saturating_sub(radius)..=(y + radius).min(height - 1) {
+                for nx in x.saturating_sub(radius)..=(x + radius).min(width - 1) {
+                    let index = indices[ny * width + nx];
+                    match counts.iter_mut().find(|(counted, _)| *counted == index) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((index, 1)),
+                    }
+                }
+            }
+            let own = counts
+                .iter()
+                .find(|(index, _)| *index == indices[i])
+                .map_or(0, |(_, count)| *count);
+            counts
+                .iter()
+                .filter(|(_, count)| *count > own)
+                .max_by_key(|(_, count)| *count)
+                .map_or(indices[i], |(index, _)| *index)
+        })
+        .collect();
+
+    recolor(indexed, &filtered);
+}
+
+/// Replaces every area of fewer than `min_size` connected pixels sharing an index
+/// with the most common index bordering it.
+///
+/// Unlike [`majority`], larger areas are never changed, so real edges and thin lines
+/// stay sharp. Pixels are connected to all 8 neighbours, so diagonal lines count as one area.
+/// Only the color channels of changed pixels are replaced, so it should run before
+/// [`crate::AlphaOptions::finish`]. Does nothing if `indexed` has no indices.
+pub fn remove_islands<T: Copy>(indexed: &mut Indexed<T>, width: usize, min_size: usize) {
+    if indexed.indices.is_empty() || width == 0 || min_size <= 1 {
+        return;
+    }
+    let height = indexed.indices.len() / width;
+    let neighbours = |i: usize| {
+        let (x, y) = (i % width, i / width);
+        (y.saturating_sub(1)..=(y + 1).min(height - 1))
+            .flat_map(move |ny| {
+                (x.saturating_sub(1)..=(x + 1).min(width - 1)).map(move |nx| ny * width + nx)
+            })
+            .filter(move |&n| n != i)
+    };
+
+    // merging an island can leave a smaller one next to it isolated, so repeat a few times
+    let mut filtered = indexed.indices.clone();
+    let mut counts: Vec<(u16, usize)> = vec![];
+    for _ in 0..4 {
+        let mut islands = small_areas(&filtered, &neighbours, min_size);
+        if islands.is_empty() {
+            break;
+        }
+        // the smallest islands go first, so specks inside a larger island join that island
+        islands.sort_by_key(Vec::len);
+        let mut changed = false;
+        for island in islands {
+            let index = filtered[island[0]];
+            counts.clear();
+            for &pixel in &island {
+                for neighbour in neighbours(pixel) {
+                    let other = filtered[neighbour];
+                    if other == index {
+                        continue;
+                    }
+                    match counts.iter_mut().find(|(counted, _)| *counted == other) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.push((other, 1)),
+                    }
+                }
+            }
+            // an island without other indices around it is the whole image
+            if let Some(&(replacement, _)) = counts.iter().max_by_key(|(_, count)| *count) {
+                for pixel in island {
+                    filtered[pixel] = replacement;
+                }
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    recolor(indexed, &filtered);
+}
+
+/// Labels every area of connected pixels sharing an index with a flood fill,
+/// returning the pixels of those smaller than `min_size`.
+fn small_areas<I: Iterator<Item = usize>>(
+    indices: &[u16],
+    neighbours: &impl Fn(usize) -> I,
+    min_size: usize,
+) -> Vec<Vec<usize>> {
+    let mut visited = vec![false; indices.len()];
+    let mut areas = vec![];
+    let mut stack = vec![];
+    for start in 0..indices.len() {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let mut area = vec![];
+        while let Some(pixel) = stack.pop() {
+            area.push(pixel);
+            for neighbour in neighbours(pixel) {
+                if !visited[neighbour] && indices[neighbour] == indices[start] {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+        if area.len() < min_size {
+            areas.push(area);
+        }
+    }
+    areas
+}
+
+/// Gives every changed pixel the new index and the color of a pixel that already had it,
+/// keeping its own alpha.
+fn recolor<T: Copy>(indexed: &mut Indexed<T>, filtered: &[u16]) {
+    let mut colors: Vec<Option<[T; 3]>> = vec![];
+    for (&index, pixel) in indexed.indices.iter().zip(indexed.pixels.chunks_exact(4)) {
+        let index = usize::from(index);
+        if colors.len() <= index {
+            colors.resize(index + 1, None);
+        }
+        colors[index].get_or_insert([pixel[0], pixel[1], pixel[2]]);
+    }
+
+    for ((index, &new), pixel) in indexed
+        .indices
+        .iter_mut()
+        .zip(filtered)
+        .zip(indexed.pixels.chunks_exact_mut(4))
+    {
+        if *index != new {
+            *index = new;
+            pixel[..3].copy_from_slice(
+                &colors[usize::from(new)].expect("filtered indices come from the image"),
+            );
+        }
+    }
+}
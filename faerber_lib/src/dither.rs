@@ -0,0 +1,395 @@
+use crate::{
+    convert_indexed, index_finder, rgba_pixels_to_labs, ColorDistance, Indexed, Lab,
+    ProtectedColors, Xorshift,
+};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::sync::OnceLock;
+
+/// Error-diffusion kernels, see <https://tannerhelland.com/2012/12/28/dithering-eleven-algorithms-source-code.html>
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorDiffusion {
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+}
+
+impl ErrorDiffusion {
+    /// Returns the `(dx, dy, weight)` entries of the kernel and the divisor of its weights.
+    #[must_use]
+    pub const fn kernel(self) -> (&'static [(isize, isize, f32)], f32) {
+        match self {
+            Self::FloydSteinberg => (&[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)], 16.0),
+            // Atkinson only propagates 6/8 of the error on purpose
+            Self::Atkinson => (
+                &[
+                    (1, 0, 1.0),
+                    (2, 0, 1.0),
+                    (-1, 1, 1.0),
+                    (0, 1, 1.0),
+                    (1, 1, 1.0),
+                    (0, 2, 1.0),
+                ],
+                8.0,
+            ),
+            Self::JarvisJudiceNinke => (
+                &[
+                    (1, 0, 7.0),
+                    (2, 0, 5.0),
+                    (-2, 1, 3.0),
+                    (-1, 1, 5.0),
+                    (0, 1, 7.0),
+                    (1, 1, 5.0),
+                    (2, 1, 3.0),
+                    (-2, 2, 1.0),
+                    (-1, 2, 3.0),
+                    (0, 2, 5.0),
+                    (1, 2, 3.0),
+                    (2, 2, 1.0),
+                ],
+                48.0,
+            ),
+            Self::Stucki => (
+                &[
+                    (1, 0, 8.0),
+                    (2, 0, 4.0),
+                    (-2, 1, 2.0),
+                    (-1, 1, 4.0),
+                    (0, 1, 8.0),
+                    (1, 1, 4.0),
+                    (2, 1, 2.0),
+                    (-2, 2, 1.0),
+                    (-1, 2, 2.0),
+                    (0, 2, 4.0),
+                    (1, 2, 2.0),
+                    (2, 2, 1.0),
+                ],
+                42.0,
+            ),
+            Self::Sierra => (
+                &[
+                    (1, 0, 5.0),
+                    (2, 0, 3.0),
+                    (-2, 1, 2.0),
+                    (-1, 1, 4.0),
+                    (0, 1, 5.0),
+                    (1, 1, 4.0),
+                    (2, 1, 2.0),
+                    (-1, 2, 2.0),
+                    (0, 2, 3.0),
+                    (1, 2, 2.0),
+                ],
+                32.0,
+            ),
+        }
+    }
+}
+
+/// Converts the image to the palette, diffusing the quantization error of each pixel
+/// onto its unprocessed neighbours. The error is computed in CIELAB and scaled by `strength`.
+///
+/// With `serpentine` scanning, every other row is processed right to left,
+/// which avoids the diagonal "worm" artifacts of plain raster order.
+/// Pixels of `protected` colors neither take nor spread any error and get the palette color
+/// of their original value, so that [`ProtectedColors::restore`] can put it back afterwards.
+#[must_use]
+pub fn error_diffusion(
+    img: &RgbaImage,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    kernel: ErrorDiffusion,
+    strength: f32,
+    serpentine: bool,
+    protected: &ProtectedColors,
+) -> Indexed {
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+    let (weights, divisor) = kernel.kernel();
+    let find_index = index_finder(convert_method, labs, None);
+
+    let mut img_labs = rgba_pixels_to_labs(img.pixels());
+    let protected = protected.pixels(img);
+    let mut indices = vec![0; width * height];
+    let mut pixels = vec![0; width * height * 4];
+
+    for y in 0..height {
+        let reverse = serpentine && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let index = y * width + x;
+
+            // accumulated error can push values outside of the valid range,
+            // protected pixels are matched by their original color instead
+            let lab = img_labs[index];
+            let lab = if protected[index] {
+                Lab::from_rgba(&img.get_pixel(x as u32, y as u32).0)
+            } else {
+                Lab::new(
+                    lab.l.clamp(0.0, 100.0),
+                    lab.a.clamp(-128.0, 127.0),
+                    lab.b.clamp(-128.0, 127.0),
+                    lab.alpha,
+                )
+            };
+            let palette_index = find_index(&lab);
+            let closest = labs
+                .get(palette_index)
+                .map_or_else(Lab::default, |color| Lab {
+                    alpha: lab.alpha,
+                    ..*color
+                });
+            indices[index] = palette_index as u16;
+            pixels[index * 4..index * 4 + 4].copy_from_slice(&closest.to_rgba());
+
+            // fully transparent pixels can hold arbitrary colors, don't spread them
+            if protected[index] || lab.alpha == 0.0 {
+                continue;
+            }
+
+            let error_l = (lab.l - closest.l) * strength / divisor;
+            let error_a = (lab.a - closest.a) * strength / divisor;
+            let error_b = (lab.b - closest.b) * strength / divisor;
+
+            for &(dx, dy, weight) in weights {
+                let Some(nx) = x.checked_add_signed(if reverse { -dx } else { dx }) else {
+                    continue;
+                };
+                let ny = y + dy as usize;
+                if nx >= width || ny >= height {
+                    continue;
+                }
+                let neighbour = &mut img_labs[ny * width + nx];
+                neighbour.l += error_l * weight;
+                neighbour.a += error_a * weight;
+                neighbour.b += error_b * weight;
+            }
+        }
+    }
+
+    Indexed { indices, pixels }
+}
+
+/// Threshold maps for ordered dithering.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ThresholdMap {
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    Bayer16,
+    /// 64x64 blue noise, generated once with the void-and-cluster method
+    BlueNoise,
+}
+
+impl ThresholdMap {
+    /// Returns the side length of the (square) map.
+    #[must_use]
+    pub const fn size(self) -> usize {
+        match self {
+            Self::Bayer2 => 2,
+            Self::Bayer4 => 4,
+            Self::Bayer8 => 8,
+            Self::Bayer16 => 16,
+            Self::BlueNoise => BLUE_NOISE_SIZE,
+        }
+    }
+
+    /// Returns the row-major thresholds of the map, each in `0.0..1.0`.
+    #[must_use]
+    pub fn thresholds(self) -> Vec<f32> {
+        match self {
+            Self::BlueNoise => blue_noise().to_vec(),
+            _ => bayer(self.size()),
+        }
+    }
+}
+
+/// Builds a Bayer matrix by interleaving the bits of `x ^ y` and `y`,
+/// with the least significant coordinate bits becoming the most significant ones.
+fn bayer(size: usize) -> Vec<f32> {
+    let bits = size.trailing_zeros();
+    let cells = (size * size) as f32;
+    (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let mut value = 0;
+            for bit in 0..bits {
+                let xb = (x >> bit) & 1;
+                let yb = (y >> bit) & 1;
+                value = (value << 2) | ((xb ^ yb) << 1) | yb;
+            }
+            (value as f32 + 0.5) / cells
+        })
+        .collect()
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+fn blue_noise() -> &'static [f32] {
+    static MAP: OnceLock<Vec<f32>> = OnceLock::new();
+    MAP.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+/// Robert Ulichney's void-and-cluster method, see
+/// <https://cv.ulichney.com/papers/1993-void-cluster.pdf>
+fn void_and_cluster(size: usize, sigma: f32) -> Vec<f32> {
+    let cells = size * size;
+
+    // gaussian falloff on a torus, indexed by the wrapped distance in x and y
+    let falloff: Vec<f32> = (0..cells)
+        .map(|i| {
+            let dx = (i % size).min(size - i % size) as f32;
+            let dy = (i / size).min(size - i / size) as f32;
+            (-dx.mul_add(dx, dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+
+    let toggle = |energy: &mut [f32], pattern: &mut [bool], index: usize| {
+        pattern[index] = !pattern[index];
+        let sign = if pattern[index] { 1.0 } else { -1.0 };
+        let (px, py) = (index % size, index / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * falloff[dy * size + dx];
+        }
+    };
+    // the tightest cluster is the set pixel with the highest energy,
+    // the largest void the unset pixel with the lowest energy
+    let tightest_cluster = |energy: &[f32], pattern: &[bool]| {
+        (0..cells)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .expect("pattern should have set pixels")
+    };
+    let largest_void = |energy: &[f32], pattern: &[bool]| {
+        (0..cells)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .expect("pattern should have unset pixels")
+    };
+
+    // seeded, so the map is the same on every run
+    let mut random = Xorshift::default();
+
+    let mut pattern = vec![false; cells];
+    let mut energy = vec![0.0; cells];
+    let initial = cells / 10;
+    let mut ones = 0;
+    while ones < initial {
+        let index = random.next() as usize % cells;
+        if !pattern[index] {
+            toggle(&mut energy, &mut pattern, index);
+            ones += 1;
+        }
+    }
+
+    // spread the initial points by moving the tightest cluster into the largest void
+    loop {
+        let cluster = tightest_cluster(&energy, &pattern);
+        toggle(&mut energy, &mut pattern, cluster);
+        let void = largest_void(&energy, &pattern);
+        toggle(&mut energy, &mut pattern, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; cells];
+
+    // phase 1: rank the initial points by removing the tightest clusters
+    let (mut phase1_energy, mut phase1_pattern) = (energy.clone(), pattern.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&phase1_energy, &phase1_pattern);
+        toggle(&mut phase1_energy, &mut phase1_pattern, cluster);
+        ranks[cluster] = rank;
+    }
+
+    // phase 2 and 3: fill the largest voids until every pixel is ranked
+    for rank in initial..cells {
+        let void = largest_void(&energy, &pattern);
+        toggle(&mut energy, &mut pattern, void);
+        ranks[void] = rank;
+    }
+
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / cells as f32)
+        .collect()
+}
+
+/// Converts the image to the palette using ordered dithering with the given threshold map.
+/// Unlike error diffusion, every pixel is processed independently and in parallel.
+///
+/// Each pixel is pushed away from its closest palette color by up to half the distance
+/// to that color's nearest neighbour in the palette, scaled by the threshold and `strength`,
+/// before searching for the closest palette color again.
+#[must_use]
+pub fn ordered(
+    img: &RgbaImage,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    map: ThresholdMap,
+    strength: f32,
+) -> Indexed {
+    if labs.len() < 2 {
+        return convert_indexed(img, convert_method, labs);
+    }
+
+    let find_index = index_finder(convert_method, labs, None);
+    let width = img.width() as usize;
+    let size = map.size();
+    let thresholds = map.thresholds();
+
+    // distance from every palette color to its nearest neighbour in the palette
+    let spacing: Vec<f32> = labs
+        .iter()
+        .enumerate()
+        .map(|(i, color)| {
+            labs.iter()
+                .enumerate()
+                .filter(|(j, _)| i != *j)
+                .map(|(_, other)| color.squared_distance(other).sqrt())
+                .fold(f32::MAX, f32::min)
+        })
+        .collect();
+
+    let (indices, pixels): (Vec<u16>, Vec<[u8; 4]>) = rgba_pixels_to_labs(img.pixels())
+        .par_iter()
+        .enumerate()
+        .map(|(i, lab)| {
+            let index = find_index(lab);
+            let closest = labs[index];
+            let distance = lab.squared_distance(&closest).sqrt();
+            if distance <= f32::EPSILON {
+                let rgba = Lab {
+                    alpha: lab.alpha,
+                    ..closest
+                }
+                .to_rgba();
+                return (index as u16, rgba);
+            }
+
+            let threshold = thresholds[(i / width % size) * size + i % width % size] - 0.5;
+            let offset = threshold * strength * spacing[index] / distance;
+            let attempt = Lab::new(
+                (lab.l - closest.l).mul_add(offset, lab.l),
+                (lab.a - closest.a).mul_add(offset, lab.a),
+                (lab.b - closest.b).mul_add(offset, lab.b),
+                lab.alpha,
+            );
+            let index = find_index(&attempt);
+            let rgba = Lab {
+                alpha: lab.alpha,
+                ..labs[index]
+            }
+            .to_rgba();
+            (index as u16, rgba)
+        })
+        .unzip();
+    Indexed {
+        indices,
+        pixels: pixels.concat(),
+    }
+}
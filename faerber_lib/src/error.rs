@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum FaerberError {
+    /// The SVG could not be read or written, `position` is the byte offset in the source.
+    Xml {
+        position: usize,
+        source: quick_xml::Error,
+    },
+    /// A color attribute or palette entry could not be parsed.
+    InvalidColor(String),
+    /// A color name is not part of the palette.
+    UnknownPaletteColor(String),
+    /// A palette file is not an object of flavours mapping color names to colors.
+    InvalidPalette(String),
+    /// An embedded image is not a valid base64 `data:` URI.
+    InvalidDataUri(String),
+    /// An image could not be decoded or encoded.
+    Image(image::ImageError),
+    /// An ICC profile is malformed or not an RGB matrix/TRC profile.
+    UnsupportedIccProfile(&'static str),
+}
+
+impl fmt::Display for FaerberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Xml { position, source } => {
+                write!(f, "invalid SVG at position {position}: {source}")
+            }
+            Self::InvalidColor(value) => write!(f, "invalid color: {value}"),
+            Self::UnknownPaletteColor(name) => write!(f, "{name} is not a color of the palette"),
+            Self::InvalidPalette(reason) => write!(f, "invalid palette file: {reason}"),
+            Self::InvalidDataUri(value) => write!(f, "invalid data URI: {value}"),
+            Self::Image(source) => write!(f, "could not process image: {source}"),
+            Self::UnsupportedIccProfile(reason) => write!(f, "unsupported ICC profile: {reason}"),
+        }
+    }
+}
+
+impl Error for FaerberError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Xml { source, .. } => Some(source),
+            Self::Image(source) => Some(source),
+            Self::InvalidColor(_)
+            | Self::UnknownPaletteColor(_)
+            | Self::InvalidPalette(_)
+            | Self::InvalidDataUri(_)
+            | Self::UnsupportedIccProfile(_) => None,
+        }
+    }
+}
+
+impl From<image::ImageError> for FaerberError {
+    fn from(error: image::ImageError) -> Self {
+        Self::Image(error)
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{Lab, Xorshift};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+
+/// Algorithms for picking the colors of a palette from an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Quantizer {
+    /// Splits the RGB cube at the median of its widest channel until there are enough boxes.
+    MedianCut,
+    /// k-means in CIELAB, seeded with k-means++, which gives the most representative colors.
+    KMeans,
+    /// Merges the least used branches of an RGB octree, which keeps rare but distinct colors.
+    Octree,
+}
+
+/// Picks up to `count` colors representing the image, ordered from most to least used.
+///
+/// Fully transparent pixels are ignored. Images with fewer unique colors return all of them.
+#[must_use]
+pub fn extract(img: &RgbaImage, count: usize, quantizer: Quantizer) -> Vec<Lab> {
+    let histogram = histogram(img);
+    if count == 0 || histogram.is_empty() {
+        return vec![];
+    }
+    let mut colors = if histogram.len() <= count {
+        histogram
+            .iter()
+            .map(|(rgb, weight)| (Lab::from_rgb(rgb), *weight))
+            .collect()
+    } else {
+        match quantizer {
+            Quantizer::MedianCut => median_cut(&histogram, count),
+            Quantizer::KMeans => k_means(&histogram, count),
+            Quantizer::Octree => octree(&histogram, count),
+        }
+    };
+    colors.sort_by_key(|(_, weight)| Reverse(*weight));
+    colors.into_iter().map(|(lab, _)| lab).collect()
+}
+
+/// The unique colors of all visible pixels, with how often they occur.
+fn histogram(img: &RgbaImage) -> Vec<([u8; 3], u64)> {
+    let mut colors: Vec<[u8; 3]> = img
+        .pixels()
+        .filter(|pixel| pixel[3] > 0)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    colors.par_sort_unstable();
+
+    let mut histogram: Vec<([u8; 3], u64)> = vec![];
+    for color in colors {
+        match histogram.last_mut() {
+            Some((last, weight)) if *last == color => *weight += 1,
+            _ => histogram.push((color, 1)),
+        }
+    }
+    histogram
+}
+
+/// The weighted average of colors in CIELAB, and their total weight.
+fn average(colors: impl Iterator<Item = (Lab, u64)>) -> (Lab, u64) {
+    let (mut l, mut a, mut b, mut total) = (0.0, 0.0, 0.0, 0);
+    for (lab, weight) in colors {
+        let weight_f = weight as f32;
+        l = lab.l.mul_add(weight_f, l);
+        a = lab.a.mul_add(weight_f, a);
+        b = lab.b.mul_add(weight_f, b);
+        total += weight;
+    }
+    let total_f = total.max(1) as f32;
+    (Lab::new(l / total_f, a / total_f, b / total_f, 1.0), total)
+}
+
+fn median_cut(histogram: &[([u8; 3], u64)], count: usize) -> Vec<(Lab, u64)> {
+    let mut boxes: Vec<Vec<([u8; 3], u64)>> = vec![histogram.to_vec()];
+    while boxes.len() < count {
+        // split the box spanning the widest range of a channel
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| {
+                let (channel, range) = widest_channel(colors);
+                (i, channel, range)
+            })
+            .max_by_key(|(_, _, range)| *range);
+        let Some((i, channel, _)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(i);
+        colors.sort_unstable_by_key(|(rgb, _)| rgb[channel]);
+        let total: u64 = colors.iter().map(|(_, weight)| weight).sum();
+        let mut seen = 0;
+        let median = colors
+            .iter()
+            .position(|(_, weight)| {
+                seen += weight;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        // keep at least one color on both sides
+        let split = (median + 1).clamp(1, colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            average(
+                colors
+                    .iter()
+                    .map(|(rgb, weight)| (Lab::from_rgb(rgb), *weight)),
+            )
+        })
+        .collect()
+}
+
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = colors.iter().map(|(rgb, _)| rgb[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap_or((0, 0))
+}
+
+fn k_means(histogram: &[([u8; 3], u64)], count: usize) -> Vec<(Lab, u64)> {
+    const ITERATIONS: usize = 32;
+    let colors: Vec<(Lab, u64)> = histogram
+        .par_iter()
+        .map(|(rgb, weight)| (Lab::from_rgb(rgb), *weight))
+        .collect();
+
+    // k-means++: every next center is picked with a probability proportional to its weight
+    // and squared distance to the closest center so far, from a fixed seed for stable palettes
+    let mut random = Xorshift::default();
+    let total: u64 = colors.iter().map(|(_, weight)| weight).sum();
+    let mut target = random.next() % total;
+    let first = colors
+        .iter()
+        .position(|(_, weight)| {
+            let found = target < *weight;
+            target = target.saturating_sub(*weight);
+            found
+        })
+        .unwrap_or(0);
+    let mut centers = vec![colors[first].0];
+    let mut distances: Vec<f32> = colors
+        .par_iter()
+        .map(|(lab, _)| lab.squared_distance(&centers[0]))
+        .collect();
+    while centers.len() < count {
+        let weighted: Vec<f64> = colors
+            .iter()
+            .zip(&distances)
+            .map(|((_, weight), distance)| *weight as f64 * f64::from(*distance))
+            .collect();
+        let sum: f64 = weighted.iter().sum();
+        if sum <= 0.0 {
+            break;
+        }
+        let mut target = random.next_f64() * sum;
+        let next = weighted
+            .iter()
+            .position(|value| {
+                target -= value;
+                target <= 0.0
+            })
+            .unwrap_or(weighted.len() - 1);
+        let center = colors[next].0;
+        centers.push(center);
+        distances
+            .par_iter_mut()
+            .zip(&colors)
+            .for_each(|(distance, (lab, _))| {
+                *distance = distance.min(lab.squared_distance(&center));
+            });
+    }
+
+    let mut assignments = vec![0; colors.len()];
+    for _ in 0..ITERATIONS {
+        let next: Vec<usize> = colors
+            .par_iter()
+            .map(|(lab, _)| closest_center(&centers, lab))
+            .collect();
+        let converged = next == assignments;
+        assignments = next;
+        for (i, center) in centers.iter_mut().enumerate() {
+            let (mean, weight) = average(
+                colors
+                    .iter()
+                    .zip(&assignments)
+                    .filter(|(_, assigned)| **assigned == i)
+                    .map(|(color, _)| *color),
+            );
+            // centers that lost all of their colors stay where they are
+            if weight > 0 {
+                *center = mean;
+            }
+        }
+        if converged {
+            break;
+        }
+    }
+
+    let mut weights = vec![0; centers.len()];
+    for ((_, weight), assigned) in colors.iter().zip(&assignments) {
+        weights[*assigned] += weight;
+    }
+    centers
+        .into_iter()
+        .zip(weights)
+        .filter(|(_, weight)| *weight > 0)
+        .collect()
+}
+
+fn closest_center(centers: &[Lab], lab: &Lab) -> usize {
+    centers
+        .iter()
+        .map(|center| lab.squared_distance(center))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(i, _)| i)
+}
+
+#[derive(Clone, Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    sum: [u64; 3],
+    weight: u64,
+    leaf: bool,
+}
+
+fn octree(histogram: &[([u8; 3], u64)], count: usize) -> Vec<(Lab, u64)> {
+    const DEPTH: usize = 8;
+    let mut nodes = vec![OctreeNode::default()];
+    // the inner nodes of every level, which can be merged into leaves
+    let mut levels: Vec<Vec<usize>> = vec![vec![]; DEPTH];
+    let mut leaves = 0;
+
+    for (rgb, weight) in histogram {
+        let mut node = 0;
+        for level in 0..DEPTH {
+            let shift = 7 - level;
+            let child = rgb.iter().fold(0, |index, channel| {
+                (index << 1) | usize::from((channel >> shift) & 1)
+            });
+            let existing = nodes[node].children[child];
+            node = existing.unwrap_or_else(|| {
+                nodes.push(OctreeNode::default());
+                let next = nodes.len() - 1;
+                nodes[node].children[child] = Some(next);
+                if level + 1 == DEPTH {
+                    nodes[next].leaf = true;
+                    leaves += 1;
+                } else {
+                    levels[level + 1].push(next);
+                }
+                next
+            });
+        }
+        let leaf = &mut nodes[node];
+        for (sum, channel) in leaf.sum.iter_mut().zip(rgb) {
+            *sum += u64::from(*channel) * weight;
+        }
+        leaf.weight += weight;
+    }
+    levels[0].push(0);
+
+    // merge the least used nodes of the deepest level into leaves until few enough are left
+    for level in (0..DEPTH).rev() {
+        let mut candidates = std::mem::take(&mut levels[level]);
+        candidates.sort_by_cached_key(|&node| subtree_weight(&nodes, node));
+        for node in candidates {
+            if leaves <= count {
+                break;
+            }
+            let children: Vec<usize> = nodes[node].children.iter().flatten().copied().collect();
+            for child in &children {
+                let (sum, weight) = (nodes[*child].sum, nodes[*child].weight);
+                nodes[*child].leaf = false;
+                let merged = &mut nodes[node];
+                for (total, value) in merged.sum.iter_mut().zip(sum) {
+                    *total += value;
+                }
+                merged.weight += weight;
+            }
+            let merged = &mut nodes[node];
+            merged.children = [None; 8];
+            merged.leaf = true;
+            leaves = leaves + 1 - children.len();
+        }
+    }
+
+    nodes
+        .iter()
+        .filter(|node| node.leaf && node.weight > 0)
+        .map(|node| {
+            let rgb = node.sum.map(|sum| (sum / node.weight) as u8);
+            (Lab::from_rgb(&rgb), node.weight)
+        })
+        .collect()
+}
+
+fn subtree_weight(nodes: &[OctreeNode], node: usize) -> u64 {
+    nodes[node].weight
+        + nodes[node]
+            .children
+            .iter()
+            .flatten()
+            .map(|child| subtree_weight(nodes, *child))
+            .sum::<u64>()
+}
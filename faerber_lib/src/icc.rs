@@ -0,0 +1,319 @@
+use crate::error::FaerberError;
+use crate::space::{linear_to_srgb, multiply, srgb_to_linear};
+use rayon::prelude::*;
+
+// the sRGB primaries adapted to the D50 connection space of ICC profiles
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.436_074_7, 0.385_064_9, 0.143_080_4],
+    [0.222_504_5, 0.716_878_6, 0.060_616_9],
+    [0.013_932_2, 0.097_104_5, 0.714_173_3],
+];
+
+/// An RGB matrix/TRC ICC profile, the kind that Display P3, Adobe RGB
+/// and most other display profiles are.
+///
+/// Only the colorants and tone curves are read, so pixels can be moved into sRGB
+/// before they are matched and back into the profile afterwards.
+#[derive(Clone, Debug)]
+pub struct IccProfile {
+    data: Vec<u8>,
+    to_srgb: [[f32; 3]; 3],
+    from_srgb: [[f32; 3]; 3],
+    curves: [Curve; 3],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Curve {
+    Gamma(f32),
+    Table(Vec<f32>),
+    /// `(scale * x + offset) ^ gamma + power_offset` from `start` on,
+    /// and `slope * x + linear_offset` below it, the most general parametric curve of ICC v4.
+    Parametric {
+        gamma: f32,
+        scale: f32,
+        offset: f32,
+        slope: f32,
+        start: f32,
+        power_offset: f32,
+        linear_offset: f32,
+    },
+}
+
+impl IccProfile {
+    /// Reads the colorants and tone curves of a profile, as embedded in a PNG or JPEG.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile is malformed or not an RGB matrix/TRC profile,
+    /// e.g. a CMYK or lookup table based profile.
+    pub fn parse(data: &[u8]) -> Result<Self, FaerberError> {
+        let malformed = || FaerberError::UnsupportedIccProfile("malformed profile");
+        if data.get(36..40) != Some(b"acsp".as_slice()) {
+            return Err(malformed());
+        }
+        if data.get(16..20) != Some(b"RGB ".as_slice()) {
+            return Err(FaerberError::UnsupportedIccProfile("not an RGB profile"));
+        }
+        if data.get(20..24) != Some(b"XYZ ".as_slice()) {
+            return Err(FaerberError::UnsupportedIccProfile(
+                "not an XYZ based profile",
+            ));
+        }
+
+        let tag_count = read_u32(data, 128).ok_or_else(malformed)? as usize;
+        let tag = |signature: &[u8; 4]| {
+            (0..tag_count)
+                .map(|i| 132 + i * 12)
+                .find(|&entry| data.get(entry..entry + 4) == Some(signature.as_slice()))
+                .and_then(|entry| {
+                    let offset = read_u32(data, entry + 4)? as usize;
+                    let size = read_u32(data, entry + 8)? as usize;
+                    data.get(offset..offset.checked_add(size)?)
+                })
+                .ok_or(FaerberError::UnsupportedIccProfile(
+                    "missing colorant or tone curve, not a matrix/TRC profile",
+                ))
+        };
+
+        let mut colorants = [[0.0; 3]; 3];
+        for (column, signature) in [b"rXYZ", b"gXYZ", b"bXYZ"].into_iter().enumerate() {
+            let xyz = read_xyz(tag(signature)?).ok_or_else(malformed)?;
+            for (row, value) in xyz.into_iter().enumerate() {
+                colorants[row][column] = value;
+            }
+        }
+        let curves = [
+            read_curve(tag(b"rTRC")?).ok_or_else(malformed)?,
+            read_curve(tag(b"gTRC")?).ok_or_else(malformed)?,
+            read_curve(tag(b"bTRC")?).ok_or_else(malformed)?,
+        ];
+        let srgb_from_xyz = invert(&SRGB_TO_XYZ).ok_or_else(malformed)?;
+        let to_srgb = product(&srgb_from_xyz, &colorants);
+        let from_srgb = invert(&to_srgb).ok_or_else(malformed)?;
+
+        Ok(Self {
+            data: data.to_vec(),
+            to_srgb,
+            from_srgb,
+            curves,
+        })
+    }
+
+    /// The raw profile, to embed it into the converted image.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Whether the profile describes sRGB, so its pixels can be used as they are.
+    #[must_use]
+    pub fn is_srgb(&self) -> bool {
+        let identity = self.to_srgb.iter().enumerate().all(|(row, values)| {
+            values.iter().enumerate().all(|(column, value)| {
+                let expected = if row == column { 1.0 } else { 0.0 };
+                (value - expected).abs() < 0.01
+            })
+        });
+        identity
+            && self.curves.iter().all(|curve| {
+                (0..=16).all(|i| {
+                    let value = i as f32 / 16.0;
+                    (curve.apply(value) - srgb_to_linear(value)).abs() < 0.01
+                })
+            })
+    }
+
+    /// Converts normalized RGBA pixels from this profile into sRGB, in place.
+    ///
+    /// Colors outside of sRGB keep channels below 0 or above 1, so they can still be
+    /// told apart when matching. Alpha is left alone.
+    pub fn to_srgb(&self, pixels: &mut [f32]) {
+        pixels.par_chunks_exact_mut(4).for_each(|pixel| {
+            let linear = [0, 1, 2].map(|i| self.curves[i].apply(pixel[i].clamp(0.0, 1.0)));
+            let srgb = multiply(&self.to_srgb, linear).map(linear_to_srgb);
+            pixel[..3].copy_from_slice(&srgb);
+        });
+    }
+
+    /// Converts normalized RGBA pixels from sRGB into this profile, in place.
+    ///
+    /// Colors outside of the profile are clipped. Alpha is left alone.
+    pub fn from_srgb(&self, pixels: &mut [f32]) {
+        pixels.par_chunks_exact_mut(4).for_each(|pixel| {
+            let linear = multiply(
+                &self.from_srgb,
+                [pixel[0], pixel[1], pixel[2]].map(srgb_to_linear),
+            );
+            for (i, value) in linear.into_iter().enumerate() {
+                pixel[i] = self.curves[i].invert(value.clamp(0.0, 1.0));
+            }
+        });
+    }
+}
+
+impl Curve {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            Self::Gamma(gamma) => x.powf(*gamma),
+            Self::Table(table) => {
+                let position = x * (table.len() - 1) as f32;
+                let index = (position as usize).min(table.len() - 2);
+                let fraction = position - index as f32;
+                (table[index + 1] - table[index]).mul_add(fraction, table[index])
+            }
+            Self::Parametric {
+                gamma,
+                scale,
+                offset,
+                slope,
+                start,
+                power_offset,
+                linear_offset,
+            } => {
+                if x >= *start {
+                    scale.mul_add(x, *offset).max(0.0).powf(*gamma) + power_offset
+                } else {
+                    slope.mul_add(x, *linear_offset)
+                }
+            }
+        }
+    }
+
+    /// Finds the input that the curve maps to `y`, which works for every curve
+    /// as long as it only ever rises.
+    fn invert(&self, y: f32) -> f32 {
+        if let Self::Gamma(gamma) = self {
+            return y.powf(gamma.recip());
+        }
+        let (mut low, mut high) = (0.0f32, 1.0f32);
+        for _ in 0..24 {
+            let middle = low.midpoint(high);
+            if self.apply(middle) < y {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        low.midpoint(high)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_s15_fixed16(data: &[u8], offset: usize) -> Option<f32> {
+    let bytes = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(i32::from_be_bytes(bytes) as f32 / 65536.0)
+}
+
+fn read_xyz(tag: &[u8]) -> Option<[f32; 3]> {
+    if tag.get(..4)? != b"XYZ " {
+        return None;
+    }
+    Some([
+        read_s15_fixed16(tag, 8)?,
+        read_s15_fixed16(tag, 12)?,
+        read_s15_fixed16(tag, 16)?,
+    ])
+}
+
+fn read_curve(tag: &[u8]) -> Option<Curve> {
+    match tag.get(..4)? {
+        b"curv" => {
+            let count = read_u32(tag, 8)? as usize;
+            let entries = tag.get(12..12 + count.checked_mul(2)?)?;
+            let entry = |bytes: &[u8]| f32::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+            Some(match count {
+                0 => Curve::Gamma(1.0),
+                1 => Curve::Gamma(entry(entries) / 256.0),
+                _ => Curve::Table(
+                    entries
+                        .chunks_exact(2)
+                        .map(|bytes| entry(bytes) / 65535.0)
+                        .collect(),
+                ),
+            })
+        }
+        b"para" => {
+            let function = u16::from_be_bytes(tag.get(8..10)?.try_into().ok()?);
+            let parameter_count = match function {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut parameters = [0.0; 7];
+            for (i, parameter) in parameters.iter_mut().take(parameter_count).enumerate() {
+                *parameter = read_s15_fixed16(tag, 12 + i * 4)?;
+            }
+            let [gamma, scale, offset, slope, start, power_offset, linear_offset] = parameters;
+            Some(match function {
+                0 => Curve::Gamma(gamma),
+                // the second and third kind are constant below where the power function starts,
+                // at 0 or at the fourth parameter
+                1 | 2 => Curve::Parametric {
+                    gamma,
+                    scale,
+                    offset,
+                    slope: 0.0,
+                    start: -offset / scale,
+                    power_offset: slope,
+                    linear_offset: slope,
+                },
+                3 => Curve::Parametric {
+                    gamma,
+                    scale,
+                    offset,
+                    slope,
+                    start,
+                    power_offset: 0.0,
+                    linear_offset: 0.0,
+                },
+                _ => Curve::Parametric {
+                    gamma,
+                    scale,
+                    offset,
+                    slope,
+                    start,
+                    power_offset,
+                    linear_offset,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+fn product(left: &[[f32; 3]; 3], right: &[[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut result = [[0.0; 3]; 3];
+    for (row, values) in result.iter_mut().enumerate() {
+        for (column, value) in values.iter_mut().enumerate() {
+            *value = (0..3).map(|i| left[row][i] * right[i][column]).sum();
+        }
+    }
+    result
+}
+
+fn invert(matrix: &[[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    // the cofactor of every entry, transposed, divided by the determinant
+    let minor = |row: usize, column: usize| {
+        let (rows, columns) = (
+            [(row + 1) % 3, (row + 2) % 3],
+            [(column + 1) % 3, (column + 2) % 3],
+        );
+        matrix[rows[0]][columns[0]].mul_add(
+            matrix[rows[1]][columns[1]],
+            -matrix[rows[0]][columns[1]] * matrix[rows[1]][columns[0]],
+        )
+    };
+    let determinant = (0..3)
+        .map(|column| matrix[0][column] * minor(0, column))
+        .sum::<f32>();
+    (determinant.abs() > f32::EPSILON)
+        .then(|| [0, 1, 2].map(|row| [0, 1, 2].map(|column| minor(column, row) / determinant)))
+}
@@ -0,0 +1,215 @@
+use crate::{closest_prepared, ColorDistance, Lab};
+
+/// Palette size from which [`crate::convert`] searches through a [`KdTree`].
+pub const MIN_PALETTE_SIZE: usize = 32;
+
+/// A k-d tree over the CIELAB values of a palette, for large palettes where
+/// a linear scan per pixel becomes too slow.
+///
+/// Lookups only compare the colors in a radius around the euclidean nearest color,
+/// given by [`ColorDistance::search_scale`], which makes them exact for the euclidean distance,
+/// DE1994, CMC and `HyAB`. Metrics without a scale compare every palette color instead.
+/// That includes DE2000: its hue rotation term has no known bound relative to the euclidean
+/// distance, so the tree can't prune candidates for it and doesn't speed it up.
+///
+/// For metrics that convert colors in [`ColorDistance::prepare`], the tree has to be built
+/// from the converted palette colors.
+#[derive(Clone, Debug)]
+pub struct KdTree {
+    palette: Vec<Lab>,
+    // palette indices, laid out so that the median of every range is the node splitting it
+    nodes: Vec<usize>,
+}
+
+impl KdTree {
+    #[must_use]
+    pub fn new(palette: &[Lab]) -> Self {
+        let mut nodes: Vec<usize> = (0..palette.len()).collect();
+        build(palette, &mut nodes, 0);
+        Self {
+            palette: palette.to_vec(),
+            nodes,
+        }
+    }
+
+    /// Finds the index of the palette color closest to `lab` by euclidean distance.
+    #[must_use]
+    pub fn nearest(&self, lab: &Lab) -> usize {
+        let mut best = (f32::MAX, 0);
+        self.search(lab, &self.nodes, 0, &mut best);
+        best.1
+    }
+
+    /// Finds the index of the palette color closest to `lab`.
+    ///
+    /// Compares every palette color for metrics without a [`ColorDistance::search_scale`].
+    #[must_use]
+    pub fn closest_index(&self, convert_method: impl ColorDistance, lab: &Lab) -> usize {
+        let Some(scale) = convert_method.search_scale(lab) else {
+            return closest_prepared(&convert_method, self.palette.iter().copied(), lab);
+        };
+        let nearest = self.nearest(lab);
+
+        let delta = |index: usize| convert_method.delta(lab, &self.palette[index]);
+        let mut closest = (delta(nearest), nearest);
+        let radius = closest.0 * scale;
+        // no other color can be closer if the radius doesn't reach past the nearest color
+        if radius * radius <= lab.squared_distance(&self.palette[nearest]) {
+            return nearest;
+        }
+        self.within(lab, radius * radius, &self.nodes, 0, &mut |index| {
+            let distance = delta(index);
+            if distance < closest.0 {
+                closest = (distance, index);
+            }
+        });
+        closest.1
+    }
+
+    /// Keeps the squared distance and index of the nearest palette color in `best`.
+    fn search(&self, lab: &Lab, nodes: &[usize], depth: usize, best: &mut (f32, usize)) {
+        if nodes.is_empty() {
+            return;
+        }
+        let median = nodes.len() / 2;
+        let index = nodes[median];
+        let color = &self.palette[index];
+
+        let distance = lab.squared_distance(color);
+        if distance < best.0 {
+            *best = (distance, index);
+        }
+
+        let axis_distance = axis(lab, depth) - axis(color, depth);
+        let (near, far) = if axis_distance < 0.0 {
+            (&nodes[..median], &nodes[median + 1..])
+        } else {
+            (&nodes[median + 1..], &nodes[..median])
+        };
+        self.search(lab, near, depth + 1, best);
+        // only descend into the other half if it can contain a closer color
+        if axis_distance * axis_distance < best.0 {
+            self.search(lab, far, depth + 1, best);
+        }
+    }
+
+    /// Calls `found` with every palette color within the squared distance `radius` of `lab`.
+    fn within(
+        &self,
+        lab: &Lab,
+        radius: f32,
+        nodes: &[usize],
+        depth: usize,
+        found: &mut impl FnMut(usize),
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let median = nodes.len() / 2;
+        let index = nodes[median];
+        let color = &self.palette[index];
+
+        if lab.squared_distance(color) <= radius {
+            found(index);
+        }
+
+        let axis_distance = axis(lab, depth) - axis(color, depth);
+        if axis_distance <= 0.0 || axis_distance * axis_distance <= radius {
+            self.within(lab, radius, &nodes[..median], depth + 1, found);
+        }
+        if axis_distance >= 0.0 || axis_distance * axis_distance <= radius {
+            self.within(lab, radius, &nodes[median + 1..], depth + 1, found);
+        }
+    }
+}
+
+fn build(palette: &[Lab], nodes: &mut [usize], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let median = nodes.len() / 2;
+    nodes.select_nth_unstable_by(median, |a, b| {
+        axis(&palette[*a], depth).total_cmp(&axis(&palette[*b], depth))
+    });
+    let (left, right) = nodes.split_at_mut(median);
+    build(palette, left, depth + 1);
+    build(palette, &mut right[1..], depth + 1);
+}
+
+const fn axis(lab: &Lab, depth: usize) -> f32 {
+    match depth % 3 {
+        0 => lab.l,
+        1 => lab.a,
+        _ => lab.b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        closest_index, index_finder, ColorDistance, ColorSpace, KdTree, Lab, Metric, Weighted,
+    };
+    use deltae::DEMethod;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_colors(rng: &mut StdRng, count: usize) -> Vec<Lab> {
+        (0..count)
+            .map(|_| Lab::from_rgb(&[rng.gen(), rng.gen(), rng.gen()]))
+            .collect()
+    }
+
+    /// Compares lookups through the tree with a linear scan, by distance since ties
+    /// can be broken differently.
+    fn assert_exact(
+        convert_method: impl ColorDistance + Copy + std::fmt::Debug,
+        palette: &[Lab],
+        colors: &[Lab],
+    ) {
+        let prepared: Vec<Lab> = palette
+            .iter()
+            .map(|color| convert_method.prepare(color))
+            .collect();
+        let find_index = index_finder(convert_method, palette, None);
+        let tree = KdTree::new(&prepared);
+        for color in colors {
+            let lab = convert_method.prepare(color);
+            let delta = |index: usize| convert_method.delta(&lab, &prepared[index]);
+            let expected = closest_index(convert_method, palette, color);
+            assert!(
+                delta(find_index(color)) <= delta(expected),
+                "{color:?} matched a farther color with {convert_method:?}"
+            );
+            assert!(
+                delta(tree.closest_index(convert_method, &lab)) <= delta(expected),
+                "{color:?} matched a farther color in the tree with {convert_method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn lookups_match_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let palette = random_colors(&mut rng, 256);
+        let colors = random_colors(&mut rng, 10_000);
+
+        let metrics = [
+            Metric::DeltaE(DEMethod::DE1976),
+            Metric::DeltaE(DEMethod::DE1994G),
+            Metric::DeltaE(DEMethod::DE1994T),
+            Metric::DeltaE(DEMethod::DE2000),
+            Metric::DeltaE(DEMethod::DECMC(1.0, 1.0)),
+            Metric::DeltaE(DEMethod::DECMC(2.0, 1.0)),
+            Metric::HyAB,
+            Metric::Euclidean(ColorSpace::CieLab),
+            Metric::Euclidean(ColorSpace::Oklab),
+            Metric::Euclidean(ColorSpace::Cam16Ucs),
+            Metric::Euclidean(ColorSpace::Din99o),
+            Metric::Euclidean(ColorSpace::Jzazbz),
+        ];
+        for metric in metrics {
+            assert_exact(metric, &palette, &colors);
+            assert_exact(Weighted::new(metric, [0.5, 1.0, 2.0]), &palette, &colors);
+        }
+    }
+}
@@ -8,23 +8,59 @@
     // clippy::unwrap_used,
     // clippy::expect_used,
 )]
-#![allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+#![allow(
+    clippy::cast_sign_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss
+)]
 
+pub mod alpha;
 pub mod custom_lab;
+pub mod despeckle;
+pub mod dither;
+pub mod error;
+pub mod extract;
+pub mod icc;
+pub mod kdtree;
+pub mod lut;
+pub mod mask;
+pub mod metric;
+pub mod overrides;
+pub mod pixel;
+pub mod protect;
+pub mod ramp;
+pub mod space;
+pub mod stream;
+pub mod superpixel;
 
+pub use crate::alpha::AlphaOptions;
 pub use crate::custom_lab::Lab;
+pub use crate::error::FaerberError;
+pub use crate::extract::Quantizer;
+pub use crate::icc::IccProfile;
+pub use crate::kdtree::KdTree;
+pub use crate::lut::Lut;
+pub use crate::mask::Region;
+pub use crate::metric::{ColorDistance, Metric, Weighted};
+pub use crate::overrides::Overrides;
+pub use crate::pixel::LabPixel;
+pub use crate::protect::ProtectedColors;
+pub use crate::ramp::RampSpace;
+pub use crate::space::ColorSpace;
+pub use crate::stream::BandConverter;
+pub use crate::superpixel::Slic;
 use base64::{engine::general_purpose::STANDARD as base64, Engine as _};
 use css_color::Srgb;
 pub use deltae::DEMethod;
-use deltae::DeltaE;
 use image::buffer::Pixels;
-use image::{Rgba, RgbaImage};
+use image::{ImageBuffer, Rgba, RgbaImage};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 use quick_xml::writer::Writer;
 use quick_xml::{events::attributes::Attribute, name::QName};
 use rayon::prelude::*;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::io::Cursor;
 
 // used for the WASM library to convert the HEX colors to CIELAB
@@ -53,155 +89,567 @@ pub fn parse_delta_e_method(method: String) -> DEMethod {
     };
 }
 
-/// # Panics
+/// Paint values that are not colors, or whose color is only known when the SVG is rendered.
+const PAINT_KEYWORDS: [&[u8]; 8] = [
+    b"none",
+    b"currentColor",
+    b"transparent",
+    b"inherit",
+    b"initial",
+    b"unset",
+    b"context-fill",
+    b"context-stroke",
+];
+
+/// Colors of an SVG that [`convert_vector_with`] doesn't match like the others.
+#[derive(Clone, Debug, Default)]
+pub struct VectorOptions {
+    /// Colors that become the palette color of their target.
+    pub overrides: Overrides,
+    /// Colors that keep their value.
+    pub protected: ProtectedColors,
+}
+
+/// Converts the color attributes and embedded images of an SVG.
 ///
-/// Panics if the SVG is invalid.
-#[must_use]
-pub fn convert_vector(source: &str, convert_method: DEMethod, labs: &Vec<Lab>) -> String {
+/// # Errors
+///
+/// Returns an error if the SVG is invalid, if a color attribute can't be parsed,
+/// or if an embedded image can't be decoded.
+pub fn convert_vector(
+    source: &str,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+) -> Result<String, FaerberError> {
+    convert_vector_with(source, convert_method, labs, &VectorOptions::default())
+}
+
+/// Like [`convert_vector`], but turns the overrides of `options` into their palette colors
+/// and keeps its protected colors as they are.
+///
+/// # Errors
+///
+/// Returns an error if the SVG is invalid, if a color attribute can't be parsed,
+/// or if an embedded image can't be decoded.
+pub fn convert_vector_with(
+    source: &str,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    options: &VectorOptions,
+) -> Result<String, FaerberError> {
     let mut reader = Reader::from_str(source);
     reader.trim_text(true);
     let mut writer = Writer::new(Cursor::new(Vec::new()));
+    let xml_error =
+        |position: usize| move |source: quick_xml::Error| FaerberError::Xml { position, source };
 
     loop {
         let event = reader.read_event();
+        let position = reader.buffer_position();
         match &event {
             Ok(Event::Start(e) | Event::Empty(e)) => {
                 let mut elem = e.to_owned();
-                let mod_attr = e.attributes().map(|attr| {
-                    let attr = attr.unwrap();
-                    match attr.key {
-                        QName(
-                            b"fill" | b"stroke" | b"stop-color" | b"flood-color"
-                            | b"lighting-color",
-                        ) => {
-                            if attr.value.starts_with(b"url(") || attr.value == b"none".as_slice() {
-                                return attr;
-                            }
+                let mod_attr = e
+                    .attributes()
+                    .map(|attr| {
+                        let attr = attr.map_err(|e| xml_error(position)(e.into()))?;
+                        Ok(match attr.key {
+                            QName(
+                                b"fill" | b"stroke" | b"stop-color" | b"flood-color"
+                                | b"lighting-color",
+                            ) => {
+                                if attr.value.starts_with(b"url(")
+                                    || PAINT_KEYWORDS
+                                        .iter()
+                                        .any(|keyword| attr.value.eq_ignore_ascii_case(keyword))
+                                {
+                                    return Ok(attr);
+                                }
 
-                            let value = String::from_utf8(attr.value.to_vec()).unwrap();
-                            let p = value.parse::<Srgb>().expect("Invalid color");
-                            let lab = Lab::from_rgb(&[
-                                (p.red * 255.0) as u8,
-                                (p.green * 255.0) as u8,
-                                (p.blue * 255.0) as u8,
-                            ]);
-                            let converted = convert_color(convert_method, labs, &lab);
-
-                            let new_color = if converted[3] == 255 {
-                                format!(
-                                    "#{:02x}{:02x}{:02x}",
-                                    converted[0], converted[1], converted[2]
-                                )
-                            } else {
-                                format!(
-                                    "#{:02x}{:02x}{:02x}{:02x}",
-                                    converted[0], converted[1], converted[2], converted[3]
-                                )
-                            };
-
-                            Attribute {
-                                key: attr.key,
-                                value: Cow::Owned(new_color.as_bytes().to_vec()),
+                                let new_color = convert_color_value(
+                                    &attr.value,
+                                    &convert_method,
+                                    labs,
+                                    options,
+                                )?;
+                                Attribute {
+                                    key: attr.key,
+                                    value: Cow::Owned(new_color.into_bytes()),
+                                }
                             }
-                        }
-                        QName(b"href") => {
-                            let value = String::from_utf8(attr.value.to_vec()).unwrap();
-                            if value.starts_with("data:image/") {
-                                let data = value.split(',').collect::<Vec<&str>>()[1];
-                                let decoded = base64.decode(data).unwrap();
-                                let image: RgbaImage =
-                                    image::load_from_memory(&decoded).unwrap().to_rgba8();
-                                let converted = convert(&image, convert_method, labs);
-                                let mut buffer = Cursor::new(Vec::new());
-                                let _ = image::write_buffer_with_format(
-                                    &mut buffer,
-                                    &converted,
-                                    image.width(),
-                                    image.height(),
-                                    image::ColorType::Rgba8,
-                                    image::ImageFormat::Png,
-                                );
-                                let encoded = base64.encode(buffer.get_ref());
-                                let href = format!("data:image/png;base64,{encoded}");
+                            QName(b"href") if attr.value.starts_with(b"data:image/") => {
+                                let href =
+                                    convert_data_uri(&attr.value, &convert_method, labs, options)?;
                                 Attribute {
                                     key: attr.key,
-                                    value: Cow::Owned(href.as_bytes().to_vec()),
+                                    value: Cow::Owned(href.into_bytes()),
                                 }
-                            } else {
-                                attr
                             }
-                        }
-                        _ => attr,
-                    }
-                });
+                            _ => attr,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, FaerberError>>()?;
                 elem.clear_attributes();
                 elem.extend_attributes(mod_attr);
-                match &event {
-                    Ok(Event::Empty(..)) => {
-                        writer.write_event(Event::Empty(elem)).unwrap();
-                    }
-                    Ok(Event::Start(..)) => {
-                        writer.write_event(Event::Start(elem)).unwrap();
-                    }
+                let elem = match &event {
+                    Ok(Event::Empty(..)) => Event::Empty(elem),
+                    Ok(Event::Start(..)) => Event::Start(elem),
                     _ => unreachable!(),
-                }
+                };
+                writer.write_event(elem).map_err(xml_error(position))?;
             }
             Ok(Event::Eof) => break,
             // we can either move or borrow the event to write, depending on your use-case
-            Ok(e) => assert!({
-                println!("closing {e:?}");
-                writer.write_event(e).is_ok()
-            }),
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Ok(e) => writer.write_event(e).map_err(xml_error(position))?,
+            Err(e) => return Err(xml_error(position)(e.clone())),
         }
     }
     let result = writer.into_inner().into_inner();
-    String::from_utf8(result).unwrap()
+    String::from_utf8(result).map_err(|e| xml_error(source.len())(e.into()))
 }
 
+/// Converts a CSS color value to the closest palette color, as a hex string.
+fn convert_color_value(
+    value: &[u8],
+    convert_method: &impl ColorDistance,
+    labs: &[Lab],
+    options: &VectorOptions,
+) -> Result<String, FaerberError> {
+    let value = String::from_utf8_lossy(value);
+    let p = value
+        .parse::<Srgb>()
+        .map_err(|_| FaerberError::InvalidColor(value.to_string()))?;
+    let lab = Lab::from_rgb(&[
+        (p.red * 255.0) as u8,
+        (p.green * 255.0) as u8,
+        (p.blue * 255.0) as u8,
+    ]);
+    // protected colors are kept, overridden ones are matched by their target
+    let converted = if options.protected.contains(&lab) {
+        lab.to_rgba()
+    } else {
+        let index = closest_index_with_overrides(convert_method, labs, &lab, &options.overrides);
+        palette_color(labs, index, lab.alpha).to_rgba()
+    };
+
+    Ok(if converted[3] == 255 {
+        format!(
+            "#{:02x}{:02x}{:02x}",
+            converted[0], converted[1], converted[2]
+        )
+    } else {
+        format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            converted[0], converted[1], converted[2], converted[3]
+        )
+    })
+}
+
+/// Converts an image embedded as a base64 `data:` URI, re-encoding it as PNG.
+fn convert_data_uri(
+    value: &[u8],
+    convert_method: &impl ColorDistance,
+    labs: &[Lab],
+    options: &VectorOptions,
+) -> Result<String, FaerberError> {
+    let value = String::from_utf8_lossy(value);
+    let invalid = || FaerberError::InvalidDataUri(value.to_string());
+    let (_, data) = value.split_once(',').ok_or_else(invalid)?;
+    let decoded = base64.decode(data).map_err(|_| invalid())?;
+    let image: RgbaImage = image::load_from_memory(&decoded)?.to_rgba8();
+    let mut converted = convert_with_overrides(&image, convert_method, labs, &options.overrides);
+    options.protected.restore(&image, &mut converted);
+    let mut buffer = Cursor::new(Vec::new());
+    image::write_buffer_with_format(
+        &mut buffer,
+        &converted,
+        image.width(),
+        image.height(),
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )?;
+    let encoded = base64.encode(buffer.get_ref());
+    Ok(format!("data:image/png;base64,{encoded}"))
+}
+
+/// A converted image: the index of the palette color of every pixel, next to its RGBA value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Indexed<T = u8> {
+    pub indices: Vec<u16>,
+    pub pixels: Vec<T>,
+}
+
+/// Converts every pixel of the image to its closest palette color.
+///
+/// Accepts 8-bit, 16-bit and floating-point images, returning the raw channels
+/// at the same precision.
 #[must_use]
-pub fn convert(img: &RgbaImage, convert_method: DEMethod, labs: &Vec<Lab>) -> Vec<u8> {
-    // convert the RGBA pixels in the image to LAB values
-    let img_labs = rgba_pixels_to_labs(img.pixels());
-
-    // loop over each LAB in the LAB-converted image:
-    // benchmarks have shown that only DeltaE 2000 benefits from parallel processing with rayon
-    return if convert_method == DEMethod::DE2000 {
-        img_labs
-            .par_iter()
-            .flat_map(|lab| convert_color(convert_method, labs, lab))
-            .collect()
+pub fn convert<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+) -> Vec<P::Subpixel> {
+    convert_indexed(img, convert_method, labs).pixels
+}
+
+/// Like [`convert`], but turns overridden colors into the palette color of their target.
+#[must_use]
+pub fn convert_with_overrides<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    overrides: &Overrides,
+) -> Vec<P::Subpixel> {
+    convert_indexed_with_overrides(img, convert_method, labs, overrides).pixels
+}
+
+/// Like [`convert`], but also returns the palette index of every pixel.
+#[must_use]
+pub fn convert_indexed<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+) -> Indexed<P::Subpixel> {
+    let parallel = convert_method.prefers_parallel();
+    convert_raw::<P>(
+        img.as_raw(),
+        &index_finder(convert_method, labs, None),
+        labs,
+        parallel,
+    )
+}
+
+/// Like [`convert_indexed`], but turns overridden colors into the palette color of their target.
+#[must_use]
+pub fn convert_indexed_with_overrides<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    overrides: &Overrides,
+) -> Indexed<P::Subpixel> {
+    let parallel = convert_method.prefers_parallel();
+    convert_raw::<P>(
+        img.as_raw(),
+        &index_finder(convert_method, labs, Some(overrides)),
+        labs,
+        parallel,
+    )
+}
+
+/// Converts raw RGBA channels through `find_index`, for whole images and bands of rows alike.
+pub(crate) fn convert_raw<P: LabPixel>(
+    raw: &[P::Subpixel],
+    find_index: &(impl Fn(&Lab) -> usize + Sync),
+    labs: &[Lab],
+    parallel: bool,
+) -> Indexed<P::Subpixel> {
+    let (indices, pixels): (Vec<u16>, Vec<[P::Subpixel; 4]>) =
+        map_unique_colors::<P, _>(raw, parallel, |lab| {
+            let index = find_index(lab);
+            let color = palette_color(labs, index, lab.alpha);
+            (index as u16, P::from_lab(&color).to_rgba().0)
+        })
+        .into_par_iter()
+        .unzip();
+    Indexed {
+        indices,
+        pixels: pixels.concat(),
+    }
+}
+
+/// Maps every pixel to a mix of its two closest palette colors, weighted by how close
+/// each of them is, which keeps anti-aliased edges and gradients smooth.
+#[must_use]
+pub fn convert_soft<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+) -> Vec<P::Subpixel> {
+    convert_soft_with_overrides(img, convert_method, labs, &Overrides::default())
+}
+
+/// Like [`convert_soft`], but turns overridden colors into the palette color
+/// of their target without mixing.
+#[must_use]
+pub fn convert_soft_with_overrides<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    convert_method: impl ColorDistance,
+    labs: &[Lab],
+    overrides: &Overrides,
+) -> Vec<P::Subpixel> {
+    let prepared: Vec<Lab> = labs
+        .iter()
+        .map(|color| convert_method.prepare(color))
+        .collect();
+    let pixels =
+        map_unique_colors::<P, _>(img.as_raw(), convert_method.prefers_parallel(), |lab| {
+            let target = overrides.get(lab);
+            let two = target
+                .is_none()
+                .then(|| closest_two(&convert_method, &prepared, &convert_method.prepare(lab)))
+                .flatten();
+            let Some(((first, first_delta), (second, second_delta))) = two else {
+                let index = closest_index(&convert_method, labs, target.unwrap_or(lab));
+                return P::from_lab(&palette_color(labs, index, lab.alpha))
+                    .to_rgba()
+                    .0;
+            };
+            let total = first_delta + second_delta;
+            let weight = if total > 0.0 {
+                second_delta / total
+            } else {
+                1.0
+            };
+            let (first, second) = (labs[first], labs[second]);
+            P::from_lab(&Lab::new(
+                (first.l - second.l).mul_add(weight, second.l),
+                (first.a - second.a).mul_add(weight, second.a),
+                (first.b - second.b).mul_add(weight, second.b),
+                lab.alpha,
+            ))
+            .to_rgba()
+            .0
+        });
+    pixels.concat()
+}
+
+/// Calls `map` once for every unique RGBA value of the raw pixels, returning the results per pixel.
+fn map_unique_colors<P: LabPixel, U: Copy + Send + Sync>(
+    raw: &[P::Subpixel],
+    parallel: bool,
+    map: impl Fn(&Lab) -> U + Sync,
+) -> Vec<U> {
+    // screenshots and flat artwork repeat the same colors over and over,
+    // so every unique RGBA value is only converted and matched once
+    let mut colors: Vec<P> = raw
+        .chunks_exact(4)
+        .map(|pixel| *P::from_slice(pixel))
+        .collect();
+    colors.par_sort_unstable_by_key(LabPixel::key);
+    colors.dedup_by_key(|color| color.key());
+
+    // convert the unique RGBA values to LAB values
+    let color_labs: Vec<Lab> = colors.iter().map(LabPixel::to_lab).collect();
+
+    let mapped: Vec<U> = if parallel {
+        color_labs.par_iter().map(&map).collect()
     } else {
-        img_labs
-            .iter()
-            .flat_map(|lab| convert_color(convert_method, labs, lab))
-            .collect()
+        color_labs.iter().map(&map).collect()
     };
+
+    // scatter the mapped colors back onto the pixels
+    raw.par_chunks_exact(4)
+        .map(|pixel| {
+            let key = P::from_slice(pixel).key();
+            mapped[colors.partition_point(|color| color.key() < key)]
+        })
+        .collect()
+}
+
+/// Mixes the converted pixels back with the original image, interpolating in CIELAB.
+///
+/// A `strength` of 0 keeps the original colors and 1 keeps the palette colors,
+/// values in between tint the image toward the palette instead of posterizing it.
+#[must_use]
+pub fn blend<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    converted: &[P::Subpixel],
+    strength: f32,
+) -> Vec<P::Subpixel> {
+    let strength = strength.clamp(0.0, 1.0);
+    img.as_raw()
+        .par_chunks_exact(4)
+        .zip(converted.par_chunks_exact(4))
+        .flat_map_iter(|(original, converted)| {
+            let original = P::from_slice(original).to_lab();
+            let converted = P::from_slice(converted).to_lab();
+            P::from_lab(&Lab::new(
+                (converted.l - original.l).mul_add(strength, original.l),
+                (converted.a - original.a).mul_add(strength, original.a),
+                (converted.b - original.b).mul_add(strength, original.b),
+                original.alpha,
+            ))
+            .to_rgba()
+            .0
+        })
+        .collect()
+}
+
+/// Keeps the lightness of every original pixel and takes only the a* and b* channels
+/// from the converted pixels, so the image is tinted by the palette without losing shading.
+#[must_use]
+pub fn preserve_lightness<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    converted: &[P::Subpixel],
+) -> Vec<P::Subpixel> {
+    img.as_raw()
+        .par_chunks_exact(4)
+        .zip(converted.par_chunks_exact(4))
+        .flat_map_iter(|(original, converted)| {
+            let original = P::from_slice(original).to_lab();
+            let converted = P::from_slice(converted).to_lab();
+            P::from_lab(&Lab::new(
+                original.l,
+                converted.a,
+                converted.b,
+                original.alpha,
+            ))
+            .to_rgba()
+            .0
+        })
+        .collect()
 }
 
+/// Converts the pixels to LAB values, converting every unique RGBA value only once.
 #[must_use]
 pub fn rgba_pixels_to_labs(img_pixels: Pixels<Rgba<u8>>) -> Vec<Lab> {
-    img_pixels.map(|pixel| Lab::from_rgba(&pixel.0)).collect()
+    let mut cache: HashMap<[u8; 4], Lab> = HashMap::new();
+    img_pixels
+        .map(|pixel| {
+            *cache
+                .entry(pixel.0)
+                .or_insert_with(|| Lab::from_rgba(&pixel.0))
+        })
+        .collect()
+}
+
+#[must_use]
+pub fn convert_color(convert_method: impl ColorDistance, palette: &[Lab], lab: &Lab) -> [u8; 4] {
+    // convert the LAB back to RGBA
+    closest_color(convert_method, palette, lab).to_rgba()
+}
+
+/// Finds the palette color closest to `lab`, carrying over the alpha of `lab`.
+#[must_use]
+pub fn closest_color(convert_method: impl ColorDistance, palette: &[Lab], lab: &Lab) -> Lab {
+    palette_color(
+        palette,
+        closest_index(convert_method, palette, lab),
+        lab.alpha,
+    )
+}
+
+/// Returns the palette color at `index` with the given alpha,
+/// or the default color if the palette is empty.
+fn palette_color(palette: &[Lab], index: usize, alpha: f32) -> Lab {
+    let Some(color) = palette.get(index) else {
+        return custom_lab::Lab::default();
+    };
+    Lab { alpha, ..*color }
 }
 
+/// Finds the index of the palette color closest to `lab`.
 #[must_use]
-pub fn convert_color(convert_method: DEMethod, palette: &Vec<Lab>, lab: &Lab) -> [u8; 4] {
+pub fn closest_index(convert_method: impl ColorDistance, palette: &[Lab], lab: &Lab) -> usize {
+    closest_prepared(
+        &convert_method,
+        palette.iter().map(|color| convert_method.prepare(color)),
+        &convert_method.prepare(lab),
+    )
+}
+
+/// Like [`closest_index`], but matches an overridden color by its target.
+#[must_use]
+pub fn closest_index_with_overrides(
+    convert_method: impl ColorDistance,
+    palette: &[Lab],
+    lab: &Lab,
+    overrides: &Overrides,
+) -> usize {
+    closest_index(convert_method, palette, overrides.get(lab).unwrap_or(lab))
+}
+
+/// Finds the index of the closest palette color, with `palette` and `lab`
+/// already converted by [`Metric::prepare`].
+pub(crate) fn closest_prepared(
+    convert_method: &impl ColorDistance,
+    palette: impl Iterator<Item = Lab>,
+    lab: &Lab,
+) -> usize {
     // keep track of the closest color
-    let mut closest_color: Lab = custom_lab::Lab::default();
+    let mut closest_index: usize = 0;
     // keep track of the closest distance measured, initially set as high as possible
     let mut closest_distance: f32 = f32::MAX;
 
     // loop over each LAB in the user's palette, and find the closest color
-    for color in palette {
-        let delta = DeltaE::new(*lab, *color, convert_method);
+    for (index, color) in palette.enumerate() {
+        let delta = convert_method.delta(lab, &color);
 
-        if delta.value() < &closest_distance {
-            closest_color = *color;
-            closest_color.alpha = lab.alpha;
-            closest_distance = *delta.value();
+        if delta < closest_distance {
+            closest_index = index;
+            closest_distance = delta;
         }
     }
 
-    // convert the LAB back to RGBA
-    closest_color.to_rgba()
+    closest_index
+}
+
+/// Finds the indices and distances of the two palette colors closest to `lab`,
+/// or `None` if the palette has fewer than two colors.
+fn closest_two(
+    convert_method: &impl ColorDistance,
+    palette: &[Lab],
+    lab: &Lab,
+) -> Option<((usize, f32), (usize, f32))> {
+    let mut first = (0, f32::MAX);
+    let mut second = (0, f32::MAX);
+    for (index, color) in palette.iter().enumerate() {
+        let delta = convert_method.delta(lab, color);
+        if delta < first.1 {
+            second = first;
+            first = (index, delta);
+        } else if delta < second.1 {
+            second = (index, delta);
+        }
+    }
+    (palette.len() >= 2).then_some((first, second))
+}
+
+/// Returns a function that finds the index of the palette color closest to a color,
+/// or to its override, searching large palettes through a [`KdTree`] instead of
+/// comparing every color.
+pub(crate) fn index_finder<'a>(
+    convert_method: impl ColorDistance + 'a,
+    palette: &[Lab],
+    overrides: Option<&'a Overrides>,
+) -> impl Fn(&Lab) -> usize + Sync + 'a {
+    // convert the palette into the working color space only once
+    let prepared: Vec<Lab> = palette
+        .iter()
+        .map(|color| convert_method.prepare(color))
+        .collect();
+    let tree = (palette.len() >= kdtree::MIN_PALETTE_SIZE).then(|| KdTree::new(&prepared));
+    move |lab| {
+        let target = overrides.and_then(|overrides| overrides.get(lab));
+        let lab = convert_method.prepare(target.unwrap_or(lab));
+        match &tree {
+            Some(tree) if convert_method.search_scale(&lab).is_some() => {
+                tree.closest_index(&convert_method, &lab)
+            }
+            _ => closest_prepared(&convert_method, prepared.iter().copied(), &lab),
+        }
+    }
+}
+
+/// A small deterministic random number generator, so that extracted palettes
+/// and dither maps don't change between runs.
+pub(crate) struct Xorshift(u64);
+
+impl Default for Xorshift {
+    fn default() -> Self {
+        Self(0x9E37_79B9_7F4A_7C15)
+    }
+}
+
+impl Xorshift {
+    pub(crate) const fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
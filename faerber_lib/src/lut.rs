@@ -0,0 +1,127 @@
+use crate::{index_finder, ColorDistance, Indexed, Lab, Overrides};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+#[cfg(not(feature = "full-lut"))]
+const BITS: u32 = 6;
+#[cfg(feature = "full-lut")]
+const BITS: u32 = 8;
+
+const SIZE: usize = 1 << BITS;
+const SHIFT: u32 = 8 - BITS;
+
+/// A lookup table from sRGB colors to the index of their closest palette color.
+///
+/// Building the table costs about as much as converting a 512x512 image,
+/// but it can then be reused for every image converted to the same palette and method.
+/// By default, every channel is quantized to 6 bits, so colors differing only in the two
+/// lowest bits share a palette color; the `full-lut` feature makes the table exact.
+/// Overridden colors are looked up by their exact value, next to the table.
+#[derive(Clone, Debug)]
+pub struct Lut {
+    colors: Vec<[u8; 3]>,
+    table: Vec<u16>,
+    overrides: HashMap<[u8; 3], u16>,
+}
+
+impl Lut {
+    /// # Panics
+    ///
+    /// Panics if the palette has more than 65536 colors.
+    #[must_use]
+    pub fn new(convert_method: impl ColorDistance, labs: &[Lab]) -> Self {
+        Self::with_overrides(convert_method, labs, &Overrides::default())
+    }
+
+    /// Like [`Lut::new`], but turns overridden colors into the palette color of their target.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the palette has more than 65536 colors.
+    #[must_use]
+    pub fn with_overrides(
+        convert_method: impl ColorDistance,
+        labs: &[Lab],
+        overrides: &Overrides,
+    ) -> Self {
+        assert!(
+            labs.len() <= 1 << 16,
+            "palette is too large for a lookup table"
+        );
+
+        let find_index = index_finder(convert_method, labs, Some(overrides));
+        let exact = overrides
+            .colors
+            .keys()
+            .map(|&rgb| (rgb, find_index(&Lab::from_rgb(&rgb)) as u16))
+            .collect();
+        // every entry is looked up by the center of the colors it covers
+        let center = |value: usize| ((value << SHIFT) | ((1 << SHIFT) >> 1)) as u8;
+        let table = (0..SIZE * SIZE * SIZE)
+            .into_par_iter()
+            .map(|i| {
+                let rgb = [
+                    center(i / SIZE / SIZE),
+                    center(i / SIZE % SIZE),
+                    center(i % SIZE),
+                ];
+                find_index(&Lab::from_rgb(&rgb)) as u16
+            })
+            .collect();
+
+        Self {
+            colors: labs.iter().map(|lab| lab.to_rgb()).collect(),
+            table,
+            overrides: exact,
+        }
+    }
+
+    /// Returns the index of the palette color closest to `rgb`, or to its override.
+    #[must_use]
+    pub fn index(&self, rgb: [u8; 3]) -> usize {
+        if let Some(&index) = self.overrides.get(&rgb) {
+            return usize::from(index);
+        }
+        let [r, g, b] = rgb.map(|c| usize::from(c >> SHIFT));
+        usize::from(self.table[(r * SIZE + g) * SIZE + b])
+    }
+
+    /// Returns the palette color closest to `rgba`, keeping its alpha.
+    #[must_use]
+    pub fn convert_color(&self, rgba: [u8; 4]) -> [u8; 4] {
+        self.color(self.index([rgba[0], rgba[1], rgba[2]]), rgba[3])
+    }
+
+    /// The palette color at `index` with the given alpha, or transparent black
+    /// for an empty palette, like [`crate::convert`].
+    fn color(&self, index: usize, alpha: u8) -> [u8; 4] {
+        self.colors
+            .get(index)
+            .map_or([0; 4], |&[r, g, b]| [r, g, b, alpha])
+    }
+
+    /// Converts every pixel of the image through the table, like [`crate::convert`].
+    #[must_use]
+    pub fn convert(&self, img: &RgbaImage) -> Vec<u8> {
+        img.pixels()
+            .flat_map(|pixel| self.convert_color(pixel.0))
+            .collect()
+    }
+
+    /// Like [`Lut::convert`], but also returns the palette index of every pixel.
+    #[must_use]
+    pub fn convert_indexed(&self, img: &RgbaImage) -> Indexed {
+        let mut pixels = Vec::with_capacity(img.as_raw().len());
+        let indices = img
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b, alpha] = pixel.0;
+                let index = self.index([r, g, b]);
+                pixels.extend_from_slice(&self.color(index, alpha));
+                index as u16
+            })
+            .collect();
+        Indexed { indices, pixels }
+    }
+}
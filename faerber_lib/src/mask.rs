@@ -0,0 +1,135 @@
+use crate::{Lab, LabPixel};
+use image::{GrayImage, ImageBuffer, Luma};
+use rayon::prelude::*;
+
+/// A shape to restrict a conversion to, drawn into a mask with [`Region::draw`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Region {
+    Rectangle {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// A polygon through the given points in pixel coordinates, which is closed automatically.
+    /// Overlapping parts are filled using the even-odd rule, its edges are anti-aliased.
+    Polygon(Vec<(f32, f32)>),
+}
+
+// samples per row and column of every pixel, when anti-aliasing polygon edges
+const SAMPLES: u32 = 4;
+
+impl Region {
+    /// Adds the region to the mask, keeping the higher value where it already had one.
+    pub fn draw(&self, mask: &mut GrayImage) {
+        let (mask_width, mask_height) = mask.dimensions();
+        match self {
+            Self::Rectangle {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let right = x.saturating_add(*width).min(mask_width);
+                let bottom = y.saturating_add(*height).min(mask_height);
+                for row in *y..bottom {
+                    for column in *x..right {
+                        mask.put_pixel(column, row, Luma([u8::MAX]));
+                    }
+                }
+            }
+            Self::Polygon(points) => {
+                if points.len() < 3 {
+                    return;
+                }
+                // only the pixels in the bounding box can be covered
+                let (left, top, right, bottom) = points.iter().fold(
+                    (
+                        f32::INFINITY,
+                        f32::INFINITY,
+                        f32::NEG_INFINITY,
+                        f32::NEG_INFINITY,
+                    ),
+                    |(left, top, right, bottom), &(x, y)| {
+                        (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                    },
+                );
+                let clamp = |value: f32, max: u32| (value.max(0.0) as u32).min(max);
+                let (left, top) = (
+                    clamp(left.floor(), mask_width),
+                    clamp(top.floor(), mask_height),
+                );
+                let (right, bottom) = (
+                    clamp(right.ceil(), mask_width),
+                    clamp(bottom.ceil(), mask_height),
+                );
+
+                // the coverage of every pixel is sampled at a 4x4 grid inside of it
+                for row in top..bottom {
+                    for column in left..right {
+                        let covered = (0..SAMPLES * SAMPLES)
+                            .filter(|sample| {
+                                let x = column as f32 + (sample % SAMPLES) as f32 / SAMPLES as f32;
+                                let y = row as f32 + (sample / SAMPLES) as f32 / SAMPLES as f32;
+                                contains(points, x + 0.125, y + 0.125)
+                            })
+                            .count() as u32;
+                        let value = (covered * 255 / (SAMPLES * SAMPLES)) as u8;
+                        let pixel = mask.get_pixel_mut(column, row);
+                        pixel.0[0] = pixel.0[0].max(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether the point is inside of the polygon, by the even-odd rule.
+fn contains(points: &[(f32, f32)], x: f32, y: f32) -> bool {
+    let mut inside = false;
+    let mut previous = points[points.len() - 1];
+    for &point in points {
+        let ((x1, y1), (x2, y2)) = (previous, point);
+        if (y1 > y) != (y2 > y) && x < (x2 - x1) * (y - y1) / (y2 - y1) + x1 {
+            inside = !inside;
+        }
+        previous = point;
+    }
+    inside
+}
+
+/// Restricts a conversion to the masked pixels of the image.
+///
+/// Keeps the converted pixels where the mask is white and the original ones where it is black,
+/// and mixes them in CIELAB in between, so soft mask edges fade into the original image.
+/// The mask must have the size of the image.
+#[must_use]
+pub fn apply_mask<P: LabPixel>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    converted: &[P::Subpixel],
+    mask: &GrayImage,
+) -> Vec<P::Subpixel> {
+    img.as_raw()
+        .par_chunks_exact(4)
+        .zip(converted.par_chunks_exact(4))
+        .zip(mask.as_raw().par_iter())
+        .flat_map_iter(|((original, converted), &value)| {
+            match value {
+                0 => return P::from_slice(original).to_rgba().0,
+                u8::MAX => return P::from_slice(converted).to_rgba().0,
+                _ => {}
+            }
+            let weight = f32::from(value) / 255.0;
+            let original = P::from_slice(original).to_lab();
+            let converted = P::from_slice(converted).to_lab();
+            P::from_lab(&Lab::new(
+                (converted.l - original.l).mul_add(weight, original.l),
+                (converted.a - original.a).mul_add(weight, original.a),
+                (converted.b - original.b).mul_add(weight, original.b),
+                (converted.alpha - original.alpha).mul_add(weight, original.alpha),
+            ))
+            .to_rgba()
+            .0
+        })
+        .collect()
+}
@@ -0,0 +1,267 @@
+use crate::space::ColorSpace;
+use crate::{DEMethod, Lab};
+use deltae::DeltaE;
+
+/// How the difference between a pixel and a palette color is measured.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// One of the `DeltaE` formulas of the `deltae` crate, in CIELAB.
+    DeltaE(DEMethod),
+    /// The euclidean distance in a working color space.
+    Euclidean(ColorSpace),
+    /// The hybrid distance of Abasi et al. in CIELAB: the absolute lightness difference
+    /// plus the euclidean distance of a* and b*, which works better for large differences.
+    HyAB,
+}
+
+impl From<DEMethod> for Metric {
+    fn from(method: DEMethod) -> Self {
+        Self::DeltaE(method)
+    }
+}
+
+/// A way of measuring how different a pixel is from a palette color.
+///
+/// Every conversion in this crate is generic over it, so custom metrics can be plugged in
+/// next to the built-in [`Metric`]s and `deltae`'s [`DEMethod`]s.
+pub trait ColorDistance: Sync {
+    /// Converts a CIELAB color into the values compared by [`ColorDistance::delta`],
+    /// e.g. into another color space. Palette colors are only converted once.
+    fn prepare(&self, lab: &Lab) -> Lab {
+        *lab
+    }
+
+    /// The difference between a pixel and a palette color, both converted by
+    /// [`ColorDistance::prepare`]. Smaller is closer.
+    fn delta(&self, reference: &Lab, sample: &Lab) -> f32;
+
+    /// How much larger than [`ColorDistance::delta`] the euclidean distance between
+    /// two prepared colors can be, with `lab` as the reference.
+    ///
+    /// Large palettes are searched through a [`crate::KdTree`] if this returns a scale,
+    /// and compared color by color otherwise.
+    fn search_scale(&self, _lab: &Lab) -> Option<f32> {
+        None
+    }
+
+    /// Whether matching colors is slow enough to benefit from parallel processing.
+    fn prefers_parallel(&self) -> bool {
+        true
+    }
+}
+
+impl<D: ColorDistance + ?Sized> ColorDistance for &D {
+    fn prepare(&self, lab: &Lab) -> Lab {
+        (**self).prepare(lab)
+    }
+
+    fn delta(&self, reference: &Lab, sample: &Lab) -> f32 {
+        (**self).delta(reference, sample)
+    }
+
+    fn search_scale(&self, lab: &Lab) -> Option<f32> {
+        (**self).search_scale(lab)
+    }
+
+    fn prefers_parallel(&self) -> bool {
+        (**self).prefers_parallel()
+    }
+}
+
+impl ColorDistance for DEMethod {
+    fn delta(&self, reference: &Lab, sample: &Lab) -> f32 {
+        Metric::DeltaE(*self).delta(reference, sample)
+    }
+
+    fn search_scale(&self, lab: &Lab) -> Option<f32> {
+        Metric::DeltaE(*self).search_scale(lab)
+    }
+
+    fn prefers_parallel(&self) -> bool {
+        Metric::DeltaE(*self).prefers_parallel()
+    }
+}
+
+impl ColorDistance for Metric {
+    fn prepare(&self, lab: &Lab) -> Lab {
+        match self {
+            Self::DeltaE(_) | Self::HyAB => *lab,
+            Self::Euclidean(space) => space.convert(lab),
+        }
+    }
+
+    fn delta(&self, reference: &Lab, sample: &Lab) -> f32 {
+        match *self {
+            Self::DeltaE(DEMethod::DECMC(lightness, chroma)) => {
+                cmc(reference, sample, lightness, chroma)
+            }
+            Self::DeltaE(DEMethod::DE1994G) => cie94(reference, sample, false),
+            Self::DeltaE(DEMethod::DE1994T) => cie94(reference, sample, true),
+            Self::DeltaE(method) => *DeltaE::new(*reference, *sample, method).value(),
+            Self::Euclidean(_) => {
+                let (dl, da, db) = (
+                    reference.l - sample.l,
+                    reference.a - sample.a,
+                    reference.b - sample.b,
+                );
+                dl.mul_add(dl, da.mul_add(da, db * db)).sqrt()
+            }
+            Self::HyAB => {
+                (reference.l - sample.l).abs()
+                    + (reference.a - sample.a).hypot(reference.b - sample.b)
+            }
+        }
+    }
+
+    /// For DE1994 and CMC the scale follows from their weighting factors and for `HyAB`
+    /// from it never being smaller than DE1976, which makes lookups exact.
+    /// DE2000 has no such bound, so it compares every palette color instead.
+    fn search_scale(&self, lab: &Lab) -> Option<f32> {
+        let chroma = lab.a.hypot(lab.b);
+        Some(match *self {
+            Self::DeltaE(DEMethod::DE2000) => return None,
+            Self::DeltaE(DEMethod::DE1994G) => 0.045f32.mul_add(chroma, 1.0),
+            Self::DeltaE(DEMethod::DE1994T) => 0.048f32.mul_add(chroma, 1.0).max(2.0),
+            Self::DeltaE(DEMethod::DECMC(lightness, chroma_weight)) => {
+                let lightness_factor = if lab.l < 16.0 {
+                    0.511
+                } else {
+                    0.040_975 * lab.l / 0.017_65f32.mul_add(lab.l, 1.0)
+                };
+                let chroma_factor = 0.0638 * chroma / 0.0131f32.mul_add(chroma, 1.0) + 0.638;
+                // the hue factor is never larger than the chroma factor
+                (lightness * lightness_factor)
+                    .max(chroma_weight * chroma_factor)
+                    .max(chroma_factor)
+            }
+            Self::DeltaE(DEMethod::DE1976) | Self::HyAB | Self::Euclidean(_) => 1.0,
+        })
+    }
+
+    fn prefers_parallel(&self) -> bool {
+        // benchmarks have shown that only DeltaE 2000 benefits from parallel processing,
+        // CAM16-UCS spends about as much time converting every pixel
+        matches!(
+            self,
+            Self::DeltaE(DEMethod::DE2000) | Self::Euclidean(ColorSpace::Cam16Ucs)
+        )
+    }
+}
+
+/// Weights the lightness, a* and b* differences between colors before they are compared
+/// by another metric, e.g. to let hue dominate the matching for monochrome palettes.
+///
+/// The difference of every channel is scaled around the pixel, so it works with
+/// every [`ColorDistance`], and its own weighting factors still use the pixel's color.
+/// A weight of 0 ignores a channel, but leaves no bound for [`ColorDistance::search_scale`],
+/// so large palettes are then compared color by color instead of through a [`crate::KdTree`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Weighted<D> {
+    pub metric: D,
+    /// Weights of the lightness, a* and b* differences, 1 keeps a channel as it is.
+    pub weights: [f32; 3],
+}
+
+impl<D: ColorDistance> Weighted<D> {
+    #[must_use]
+    pub const fn new(metric: D, weights: [f32; 3]) -> Self {
+        Self { metric, weights }
+    }
+}
+
+impl<D: ColorDistance> ColorDistance for Weighted<D> {
+    fn prepare(&self, lab: &Lab) -> Lab {
+        self.metric.prepare(lab)
+    }
+
+    fn delta(&self, reference: &Lab, sample: &Lab) -> f32 {
+        let [l, a, b] = self.weights;
+        let weighted = Lab {
+            l: (sample.l - reference.l).mul_add(l, reference.l),
+            a: (sample.a - reference.a).mul_add(a, reference.a),
+            b: (sample.b - reference.b).mul_add(b, reference.b),
+            alpha: sample.alpha,
+        };
+        self.metric.delta(reference, &weighted)
+    }
+
+    fn search_scale(&self, lab: &Lab) -> Option<f32> {
+        // a difference can shrink by the smallest weight before the metric sees it
+        let smallest = self.weights.into_iter().fold(f32::MAX, f32::min);
+        if smallest <= 0.0 {
+            return None;
+        }
+        self.metric.search_scale(lab).map(|scale| scale / smallest)
+    }
+
+    fn prefers_parallel(&self) -> bool {
+        self.metric.prefers_parallel()
+    }
+}
+
+/// DE1994 for graphics or textiles, like `deltae`, but without the square root
+/// of a negative hue difference, see [`cmc`].
+fn cie94(reference: &Lab, sample: &Lab, textiles: bool) -> f32 {
+    let (lightness, chroma_weight, hue_weight): (f32, f32, f32) = if textiles {
+        (2.0, 0.048, 0.014)
+    } else {
+        (1.0, 0.045, 0.015)
+    };
+    let reference_chroma = reference.a.hypot(reference.b);
+    let delta_l = (reference.l - sample.l) / lightness;
+    let delta_c = reference_chroma - sample.a.hypot(sample.b);
+    let (delta_a, delta_b) = (reference.a - sample.a, reference.b - sample.b);
+    let delta_h_squared = delta_c
+        .mul_add(-delta_c, delta_a.mul_add(delta_a, delta_b * delta_b))
+        .max(0.0);
+
+    let s_c = chroma_weight.mul_add(reference_chroma, 1.0);
+    let s_h = hue_weight.mul_add(reference_chroma, 1.0);
+    let weighted_c = delta_c / s_c;
+    delta_l
+        .mul_add(
+            delta_l,
+            weighted_c.mul_add(weighted_c, delta_h_squared / (s_h * s_h)),
+        )
+        .sqrt()
+}
+
+/// CMC l:c, like `deltae`, but without taking the square root of a negative hue difference
+/// when rounding errors make it slightly negative, which happens for every gray color.
+fn cmc(reference: &Lab, sample: &Lab, lightness: f32, chroma: f32) -> f32 {
+    let reference_chroma = reference.a.hypot(reference.b);
+    let delta_l = reference.l - sample.l;
+    let delta_c = reference_chroma - sample.a.hypot(sample.b);
+    let (delta_a, delta_b) = (reference.a - sample.a, reference.b - sample.b);
+    let delta_h_squared = delta_c
+        .mul_add(-delta_c, delta_a.mul_add(delta_a, delta_b * delta_b))
+        .max(0.0);
+
+    let s_l = if reference.l < 16.0 {
+        0.511
+    } else {
+        0.040_975 * reference.l / 0.017_65f32.mul_add(reference.l, 1.0)
+    };
+    let s_c = 0.0638 * reference_chroma / 0.0131f32.mul_add(reference_chroma, 1.0) + 0.638;
+    let hue = reference
+        .b
+        .atan2(reference.a)
+        .to_degrees()
+        .rem_euclid(360.0);
+    let f = (reference_chroma.powi(4) / (reference_chroma.powi(4) + 1900.0)).sqrt();
+    let t = if (164.0..345.0).contains(&hue) {
+        0.56 + (0.2 * (hue + 168.0).to_radians().cos()).abs()
+    } else {
+        0.36 + (0.4 * (hue + 35.0).to_radians().cos()).abs()
+    };
+    let s_h = s_c * f.mul_add(t, 1.0 - f);
+
+    let weighted_l = delta_l / (lightness * s_l);
+    let weighted_c = delta_c / (chroma * s_c);
+    weighted_l
+        .mul_add(
+            weighted_l,
+            weighted_c.mul_add(weighted_c, delta_h_squared / (s_h * s_h)),
+        )
+        .sqrt()
+}
@@ -0,0 +1,28 @@
+use crate::Lab;
+use std::collections::HashMap;
+
+/// Source colors that become a specific palette color before any other color is matched,
+/// e.g. to turn the `#ff0000` of an icon into a palette's red even if its orange is closer.
+///
+/// Pixels are compared by their sRGB value, ignoring alpha, and become the palette color
+/// closest to their target, which is the target itself if it's part of the palette.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    pub colors: HashMap<[u8; 3], Lab>,
+}
+
+impl Overrides {
+    #[must_use]
+    pub const fn new(colors: HashMap<[u8; 3], Lab>) -> Self {
+        Self { colors }
+    }
+
+    /// The target of the color, if its sRGB value is overridden.
+    #[must_use]
+    pub fn get(&self, lab: &Lab) -> Option<&Lab> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        self.colors.get(&lab.to_rgb())
+    }
+}
@@ -0,0 +1,78 @@
+use crate::Lab;
+use image::{Pixel, Primitive, Rgba};
+use lab::Lab as LabBase;
+
+/// RGBA pixels that can be converted, which are converted to and from CIELAB
+/// at their own precision.
+///
+/// Implemented for 8-bit, 16-bit and floating-point images, where floating-point
+/// channels are sRGB encoded and normalized to `0.0..=1.0`, like the ones `image` converts to.
+pub trait LabPixel: Pixel<Subpixel = Self::Channel> + Send + Sync {
+    type Channel: Primitive + Send + Sync;
+    /// A totally ordered value identifying the pixel, used to convert repeated colors only once.
+    type Key: Ord + Copy + Send + Sync;
+
+    fn key(&self) -> Self::Key;
+
+    fn to_lab(&self) -> Lab;
+
+    /// Converts a CIELAB color back, rounding it to the closest representable color.
+    fn from_lab(lab: &Lab) -> Self;
+}
+
+impl LabPixel for Rgba<u8> {
+    type Channel = u8;
+    type Key = [u8; 4];
+
+    fn key(&self) -> Self::Key {
+        self.0
+    }
+
+    fn to_lab(&self) -> Lab {
+        Lab::from_rgba(&self.0)
+    }
+
+    fn from_lab(lab: &Lab) -> Self {
+        Self(lab.to_rgba())
+    }
+}
+
+impl LabPixel for Rgba<u16> {
+    type Channel = u16;
+    type Key = [u16; 4];
+
+    fn key(&self) -> Self::Key {
+        self.0
+    }
+
+    fn to_lab(&self) -> Lab {
+        let [r, g, b, alpha] = self
+            .0
+            .map(|channel| f32::from(channel) / f32::from(u16::MAX));
+        Lab::from(LabBase::from_rgb_normalized(&[r, g, b]), alpha)
+    }
+
+    fn from_lab(lab: &Lab) -> Self {
+        let [r, g, b] = LabBase::from(*lab).to_rgb_normalized();
+        Self([r, g, b, lab.alpha].map(|channel| (channel * f32::from(u16::MAX)).round() as u16))
+    }
+}
+
+impl LabPixel for Rgba<f32> {
+    type Channel = f32;
+    type Key = [u32; 4];
+
+    fn key(&self) -> Self::Key {
+        self.0.map(f32::to_bits)
+    }
+
+    fn to_lab(&self) -> Lab {
+        let [r, g, b, alpha] = self.0;
+        Lab::from(LabBase::from_rgb_normalized(&[r, g, b]), alpha)
+    }
+
+    fn from_lab(lab: &Lab) -> Self {
+        let [r, g, b] = LabBase::from(*lab).to_rgb_normalized();
+        Self([r, g, b, lab.alpha])
+    }
+}
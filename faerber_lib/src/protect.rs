@@ -0,0 +1,70 @@
+use crate::{map_unique_colors, ColorDistance, DEMethod, Lab, LabPixel};
+use image::ImageBuffer;
+use rayon::prelude::*;
+
+/// Colors that are never remapped, like the colors of a logo or brand.
+///
+/// Pixels within `tolerance` of one of the colors, measured as ΔE2000,
+/// keep their original value after the conversion.
+#[derive(Clone, Debug, Default)]
+pub struct ProtectedColors {
+    pub colors: Vec<Lab>,
+    pub tolerance: f32,
+}
+
+impl ProtectedColors {
+    #[must_use]
+    pub const fn new(colors: Vec<Lab>, tolerance: f32) -> Self {
+        Self { colors, tolerance }
+    }
+
+    /// Whether the color is close enough to a protected color to be left alone.
+    #[must_use]
+    pub fn contains(&self, lab: &Lab) -> bool {
+        self.colors
+            .iter()
+            .any(|color| DEMethod::DE2000.delta(color, lab) <= self.tolerance)
+    }
+
+    /// Like [`crate::convert_color`], but returns protected colors as they are.
+    #[must_use]
+    pub fn convert_color(
+        &self,
+        convert_method: impl ColorDistance,
+        palette: &[Lab],
+        lab: &Lab,
+    ) -> [u8; 4] {
+        if self.contains(lab) {
+            lab.to_rgba()
+        } else {
+            crate::convert_color(convert_method, palette, lab)
+        }
+    }
+
+    /// Puts the original value back into every converted pixel whose original color
+    /// is protected, returning how many pixels were restored.
+    pub fn restore<P: LabPixel>(
+        &self,
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+        converted: &mut [P::Subpixel],
+    ) -> usize {
+        if self.colors.is_empty() {
+            return 0;
+        }
+        converted
+            .par_chunks_exact_mut(4)
+            .zip(img.as_raw().par_chunks_exact(4))
+            .zip(self.pixels(img))
+            .filter(|(_, protected)| *protected)
+            .map(|((pixel, original), _)| pixel.copy_from_slice(original))
+            .count()
+    }
+
+    /// Whether every pixel of the image has a protected color.
+    pub(crate) fn pixels<P: LabPixel>(&self, img: &ImageBuffer<P, Vec<P::Subpixel>>) -> Vec<bool> {
+        if self.colors.is_empty() {
+            return vec![false; img.as_raw().len() / 4];
+        }
+        map_unique_colors::<P, _>(img.as_raw(), true, |lab| self.contains(lab))
+    }
+}
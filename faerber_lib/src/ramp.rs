@@ -0,0 +1,111 @@
+use crate::space::{lab_to_xyz, linear_to_srgb, multiply, oklab, oklab_to_xyz};
+use crate::Lab;
+
+// linear sRGB from XYZ, for the D65 white of the `lab` crate
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.240_812_4, -1.537_308_4, -0.498_586_5],
+    [-0.969_243, 1.875_966_3, 0.041_555_03],
+    [0.055_638_4, -0.204_007_46, 1.057_129_6],
+];
+
+/// Color spaces that palette ramps can be interpolated in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RampSpace {
+    /// CIELAB, where ramps toward white tend to drift toward purple for blues.
+    CieLab,
+    /// Oklab, where the hue of every shade stays close to its palette color.
+    Oklab,
+}
+
+impl RampSpace {
+    fn coordinates(self, lab: &Lab) -> [f32; 3] {
+        match self {
+            Self::CieLab => [lab.l, lab.a, lab.b],
+            Self::Oklab => oklab(lab_to_xyz(lab)),
+        }
+    }
+
+    fn to_linear_srgb(self, color: [f32; 3]) -> [f32; 3] {
+        let xyz = match self {
+            Self::CieLab => lab_to_xyz(&Lab::new(color[0], color[1], color[2], 1.0)),
+            Self::Oklab => oklab_to_xyz(color),
+        };
+        multiply(&XYZ_TO_SRGB, xyz)
+    }
+
+    /// Moves a color into the sRGB gamut by lowering its chroma, keeping its lightness and hue.
+    fn clip(self, [l, a, b]: [f32; 3]) -> [f32; 3] {
+        const TOLERANCE: f32 = 1e-4;
+        let in_gamut = |scale: f32| {
+            self.to_linear_srgb([l, a * scale, b * scale])
+                .iter()
+                .all(|channel| (-TOLERANCE..=1.0 + TOLERANCE).contains(channel))
+        };
+        if in_gamut(1.0) {
+            return [l, a, b];
+        }
+        let (mut low, mut high) = (0.0f32, 1.0f32);
+        for _ in 0..24 {
+            let middle = low.midpoint(high);
+            if in_gamut(middle) {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        [l, a * low, b * low]
+    }
+
+    fn to_lab(self, color: [f32; 3]) -> Lab {
+        let srgb = self
+            .to_linear_srgb(color)
+            .map(|channel| linear_to_srgb(channel.clamp(0.0, 1.0)));
+        Lab::from(lab::Lab::from_rgb_normalized(&srgb), 1.0)
+    }
+}
+
+/// Returns `shades` evenly spaced colors between `from` and `to`, excluding both,
+/// interpolated in `space` and clipped to the sRGB gamut.
+#[must_use]
+pub fn ramp(from: &Lab, to: &Lab, shades: usize, space: RampSpace) -> Vec<Lab> {
+    let (start, end) = (space.coordinates(from), space.coordinates(to));
+    (1..=shades)
+        .map(|i| {
+            let t = i as f32 / (shades + 1) as f32;
+            let color =
+                [0, 1, 2].map(|channel| (end[channel] - start[channel]).mul_add(t, start[channel]));
+            space.to_lab(space.clip(color))
+        })
+        .collect()
+}
+
+/// Extends a palette with `shades` intermediate colors between every color and the
+/// palette's background and foreground, for smoother results with small palettes.
+///
+/// The darkest color is taken as the background and the lightest as the foreground,
+/// which also get a ramp between each other. The palette colors come first, in their
+/// original order, so their indices don't change.
+///
+/// Palette indices are 16-bit, so the expanded palette has to stay below 65536 colors
+/// to be used for conversions.
+#[must_use]
+pub fn expand_palette(labs: &[Lab], shades: usize, space: RampSpace) -> Vec<Lab> {
+    let by_lightness = |a: &&Lab, b: &&Lab| a.l.total_cmp(&b.l);
+    let (Some(background), Some(foreground)) = (
+        labs.iter().min_by(by_lightness),
+        labs.iter().max_by(by_lightness),
+    ) else {
+        return vec![];
+    };
+
+    let mut expanded = labs.to_vec();
+    expanded.extend(ramp(background, foreground, shades, space));
+    for lab in labs {
+        if std::ptr::eq(lab, background) || std::ptr::eq(lab, foreground) {
+            continue;
+        }
+        expanded.extend(ramp(lab, background, shades, space));
+        expanded.extend(ramp(lab, foreground, shades, space));
+    }
+    expanded
+}
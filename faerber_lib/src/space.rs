@@ -0,0 +1,227 @@
+use crate::Lab;
+
+// reference white of the `lab` crate, D65
+const WHITE_X: f32 = 0.950_449_2;
+const WHITE_Z: f32 = 1.088_916_6;
+
+/// Color spaces that palette colors can be matched in.
+///
+/// Colors are always passed around as CIELAB values, [`ColorSpace::convert`] maps them
+/// into the working space, stored in a [`Lab`] with the lightness scaled to `0..=100`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// CIELAB, the space the `lab` crate and `deltae` work in.
+    CieLab,
+    /// Oklab, with more uniform hues than CIELAB, especially for blues and purples.
+    Oklab,
+    /// CAM16-UCS, under average surround and the D65 white point.
+    Cam16Ucs,
+    /// `DIN99o`, a logarithmic compression of CIELAB, where the euclidean distance is `ΔE99o`.
+    Din99o,
+    /// `Jzazbz`, for a reference white of 203 cd/m², where the euclidean distance is `ΔEz`.
+    Jzazbz,
+}
+
+impl ColorSpace {
+    /// Converts a CIELAB color into this color space, keeping its alpha.
+    #[must_use]
+    pub fn convert(self, lab: &Lab) -> Lab {
+        let [l, a, b] = match self {
+            Self::CieLab => return *lab,
+            Self::Oklab => oklab(lab_to_xyz(lab)),
+            Self::Cam16Ucs => cam16_ucs(lab_to_xyz(lab)),
+            Self::Din99o => din99o(lab),
+            Self::Jzazbz => jzazbz(lab_to_xyz(lab)),
+        };
+        Lab::new(l, a, b, lab.alpha)
+    }
+}
+
+pub(crate) fn lab_to_xyz(lab: &Lab) -> [f32; 3] {
+    const EPSILON: f32 = 216.0 / 24389.0;
+    const KAPPA: f32 = 24389.0 / 27.0;
+    let inverse = |t: f32| {
+        let cubed = t * t * t;
+        if cubed > EPSILON {
+            cubed
+        } else {
+            116.0f32.mul_add(t, -16.0) / KAPPA
+        }
+    };
+    let fy = (lab.l + 16.0) / 116.0;
+    let fx = lab.a / 500.0 + fy;
+    let fz = fy - lab.b / 200.0;
+    [inverse(fx) * WHITE_X, inverse(fy), inverse(fz) * WHITE_Z]
+}
+
+pub(crate) fn multiply(matrix: &[[f32; 3]; 3], [x, y, z]: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0].mul_add(x, row[1].mul_add(y, row[2] * z)))
+}
+
+/// Decodes an sRGB channel, mirrored for negative values, which extended ranges have.
+pub(crate) fn srgb_to_linear(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let linear = if magnitude <= 0.040_45 {
+        magnitude / 12.92
+    } else {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    };
+    linear.copysign(value)
+}
+
+/// Encodes a linear channel with the sRGB transfer function, the inverse of [`srgb_to_linear`].
+pub(crate) fn linear_to_srgb(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let encoded = if magnitude <= 0.003_130_8 {
+        magnitude * 12.92
+    } else {
+        1.055f32.mul_add(magnitude.powf(1.0 / 2.4), -0.055)
+    };
+    encoded.copysign(value)
+}
+
+/// Oklab from XYZ, scaled by 100 so its lightness matches the CIELAB range.
+pub(crate) fn oklab(xyz: [f32; 3]) -> [f32; 3] {
+    const XYZ_TO_LMS: [[f32; 3]; 3] = [
+        [0.818_933, 0.361_866_74, -0.128_859_71],
+        [0.032_984_544, 0.929_311_9, 0.036_145_64],
+        [0.048_200_3, 0.264_366_27, 0.633_851_7],
+    ];
+    const LMS_TO_OKLAB: [[f32; 3]; 3] = [
+        [0.210_454_26, 0.793_617_8, -0.004_072_047],
+        [1.977_998_5, -2.428_592_2, 0.450_593_7],
+        [0.025_904_037, 0.782_771_77, -0.808_675_77],
+    ];
+    let lms = multiply(&XYZ_TO_LMS, xyz).map(f32::cbrt);
+    multiply(&LMS_TO_OKLAB, lms).map(|value| value * 100.0)
+}
+
+/// XYZ from Oklab scaled by 100, the inverse of [`oklab`].
+pub(crate) fn oklab_to_xyz(oklab: [f32; 3]) -> [f32; 3] {
+    const OKLAB_TO_LMS: [[f32; 3]; 3] = [
+        [1.0, 0.396_337_78, 0.215_803_76],
+        [1.0, -0.105_561_346, -0.063_854_17],
+        [1.0, -0.089_484_18, -1.291_485_5],
+    ];
+    const LMS_TO_XYZ: [[f32; 3]; 3] = [
+        [1.227_013_8, -0.557_8, 0.281_256_14],
+        [-0.040_580_18, 1.112_256_9, -0.071_676_68],
+        [-0.076_381_28, -0.421_481_97, 1.586_163_2],
+    ];
+    let lms = multiply(&OKLAB_TO_LMS, oklab.map(|value| value / 100.0));
+    multiply(&LMS_TO_XYZ, lms.map(|value| value * value * value))
+}
+
+/// CAM16-UCS J', a' and b' from XYZ, for an adapting luminance of 64 lux / π / 5,
+/// a background luminance factor of 20 and average surround.
+fn cam16_ucs(xyz: [f32; 3]) -> [f32; 3] {
+    const XYZ_TO_CAT16: [[f32; 3]; 3] = [
+        [0.401_288, 0.650_173, -0.051_461],
+        [-0.250_268, 1.204_414, 0.045_854],
+        [-0.002_079, 0.048_952, 0.953_127],
+    ];
+    const ADAPTING_LUMINANCE: f32 = 64.0 / std::f32::consts::PI / 5.0;
+    const BACKGROUND: f32 = 20.0;
+    // average surround
+    const F: f32 = 1.0;
+    const C: f32 = 0.69;
+    const NC: f32 = 1.0;
+
+    let white = multiply(&XYZ_TO_CAT16, [WHITE_X * 100.0, 100.0, WHITE_Z * 100.0]);
+    let degree = (F * (-1.0f32 / 3.6).mul_add(((-ADAPTING_LUMINANCE - 42.0) / 92.0).exp(), 1.0))
+        .clamp(0.0, 1.0);
+    let discount = white.map(|channel| degree * 100.0 / channel + 1.0 - degree);
+
+    let k4 = (1.0 / 5.0f32.mul_add(ADAPTING_LUMINANCE, 1.0)).powi(4);
+    let luminance_adaptation = (0.2 * k4).mul_add(
+        5.0 * ADAPTING_LUMINANCE,
+        0.1 * (1.0 - k4).powi(2) * (5.0 * ADAPTING_LUMINANCE).cbrt(),
+    );
+    let background = BACKGROUND / 100.0;
+    let exponent = C * (1.48 + background.sqrt());
+    let induction = 0.725 * background.powf(-0.2);
+
+    let compress = |channels: [f32; 3]| {
+        let mut adapted = [0.0; 3];
+        for ((adapted, channel), discount) in adapted.iter_mut().zip(channels).zip(discount) {
+            let response = (luminance_adaptation * (channel * discount).abs() / 100.0).powf(0.42);
+            *adapted = (400.0 * response / (response + 27.13)).copysign(channel) + 0.1;
+        }
+        adapted
+    };
+    let achromatic =
+        |[red, green, blue]: [f32; 3]| 2.0f32.mul_add(red, 0.05f32.mul_add(blue, green)) - 0.305;
+
+    let white_achromatic = achromatic(compress(white)) * induction;
+    let [red, green, blue] = compress(multiply(&XYZ_TO_CAT16, xyz.map(|value| value * 100.0)));
+
+    let a = (-12.0f32 / 11.0).mul_add(green, red) + blue / 11.0;
+    let b = 2.0f32.mul_add(-blue, red + green) / 9.0;
+    let hue = b.atan2(a);
+    let eccentricity = 0.25 * ((hue + 2.0).cos() + 3.8);
+
+    let lightness = 100.0
+        * (achromatic([red, green, blue]) * induction / white_achromatic)
+            .max(0.0)
+            .powf(exponent);
+    let t = (50000.0 / 13.0 * NC * induction * eccentricity * a.hypot(b))
+        / 1.05f32.mul_add(blue, red + green);
+    let chroma =
+        t.powf(0.9) * (lightness / 100.0).sqrt() * (1.64 - 0.29f32.powf(background)).powf(0.73);
+    let colorfulness = chroma * luminance_adaptation.powf(0.25);
+
+    let ucs_lightness = 1.7 * lightness / 0.007f32.mul_add(lightness, 1.0);
+    let ucs_colorfulness = 0.0228f32.mul_add(colorfulness, 1.0).ln() / 0.0228;
+    [
+        ucs_lightness,
+        ucs_colorfulness * hue.cos(),
+        ucs_colorfulness * hue.sin(),
+    ]
+}
+
+/// `DIN99o` from CIELAB, as specified in DIN 6176.
+fn din99o(lab: &Lab) -> [f32; 3] {
+    let (sin, cos) = 26.0f32.to_radians().sin_cos();
+    let lightness = 303.67 * 0.0039f32.mul_add(lab.l, 1.0).ln();
+    let e = lab.a.mul_add(cos, lab.b * sin);
+    let f = 0.83 * lab.b.mul_add(cos, -lab.a * sin);
+    let chroma = 0.075f32.mul_add(e.hypot(f), 1.0).ln() / 0.0435;
+    let hue = f.atan2(e) + 26.0f32.to_radians();
+    [lightness, chroma * hue.cos(), chroma * hue.sin()]
+}
+
+/// `Jzazbz` from XYZ, scaled so that the reference white has a lightness of 100.
+fn jzazbz(xyz: [f32; 3]) -> [f32; 3] {
+    const WHITE_LUMINANCE: f32 = 203.0;
+    // Jz of the reference white
+    const WHITE_LIGHTNESS: f32 = 0.222_066;
+    const XYZ_TO_LMS: [[f32; 3]; 3] = [
+        [0.414_789_7, 0.579_999, 0.014_648],
+        [-0.201_51, 1.120_649, 0.053_100_8],
+        [-0.016_600_8, 0.264_8, 0.668_479_9],
+    ];
+    const LMS_TO_IAB: [[f32; 3]; 3] = [
+        [0.5, 0.5, 0.0],
+        [3.524, -4.066_708, 0.542_708],
+        [0.199_076, 1.096_799, -1.295_875],
+    ];
+    const D: f32 = -0.56;
+    const D0: f32 = 1.629_55e-11;
+
+    let [x, y, z] = xyz.map(|value| value * WHITE_LUMINANCE);
+    let adjusted = [
+        1.15f32.mul_add(x, -0.15 * z),
+        0.66f32.mul_add(y, 0.34 * x),
+        z,
+    ];
+    let perceptual = multiply(&XYZ_TO_LMS, adjusted).map(|value| {
+        // the PQ transfer function of SMPTE ST 2084
+        let value = (value / 10000.0).max(0.0).powf(2610.0 / 16384.0);
+        ((2413.0f32 / 128.0).mul_add(value, 3424.0 / 4096.0)
+            / (2392.0f32 / 128.0).mul_add(value, 1.0))
+        .powf(1.7 * 2523.0 / 32.0)
+    });
+    let [intensity, red_green, yellow_blue] = multiply(&LMS_TO_IAB, perceptual);
+    let lightness = (1.0 + D) * intensity / D.mul_add(intensity, 1.0) - D0;
+    [lightness, red_green, yellow_blue].map(|value| value * 100.0 / WHITE_LIGHTNESS)
+}
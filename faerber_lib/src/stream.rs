@@ -0,0 +1,62 @@
+use crate::{convert_raw, index_finder, ColorDistance, Indexed, Lab, LabPixel, Overrides};
+use image::Rgba;
+
+/// Converts an image one band of rows at a time, for images too large to hold in memory.
+///
+/// The palette is prepared once, so memory use only depends on the size of a band,
+/// and converting every band gives the same pixels as [`crate::convert`] on the whole image.
+pub struct BandConverter<'a> {
+    find_index: Box<dyn Fn(&Lab) -> usize + Sync + 'a>,
+    labs: &'a [Lab],
+    parallel: bool,
+}
+
+impl<'a> BandConverter<'a> {
+    #[must_use]
+    pub fn new(convert_method: impl ColorDistance + 'a, labs: &'a [Lab]) -> Self {
+        Self::with_finder(convert_method, labs, None)
+    }
+
+    /// Like [`BandConverter::new`], but turns overridden colors into the palette color
+    /// of their target.
+    #[must_use]
+    pub fn with_overrides(
+        convert_method: impl ColorDistance + 'a,
+        labs: &'a [Lab],
+        overrides: &'a Overrides,
+    ) -> Self {
+        Self::with_finder(convert_method, labs, Some(overrides))
+    }
+
+    fn with_finder(
+        convert_method: impl ColorDistance + 'a,
+        labs: &'a [Lab],
+        overrides: Option<&'a Overrides>,
+    ) -> Self {
+        let parallel = convert_method.prefers_parallel();
+        Self {
+            find_index: Box::new(index_finder(convert_method, labs, overrides)),
+            labs,
+            parallel,
+        }
+    }
+
+    /// Converts a band of raw RGBA pixels, with any number of rows and channels
+    /// of any type supported by [`LabPixel`].
+    #[must_use]
+    pub fn convert<T>(&self, band: &[T]) -> Vec<T>
+    where
+        Rgba<T>: LabPixel<Channel = T>,
+    {
+        self.convert_indexed(band).pixels
+    }
+
+    /// Like [`BandConverter::convert`], but also returns the palette index of every pixel.
+    #[must_use]
+    pub fn convert_indexed<T>(&self, band: &[T]) -> Indexed<T>
+    where
+        Rgba<T>: LabPixel<Channel = T>,
+    {
+        convert_raw::<Rgba<T>>(band, &self.find_index, self.labs, self.parallel)
+    }
+}
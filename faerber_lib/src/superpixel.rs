@@ -0,0 +1,201 @@
+use crate::{index_finder, map_unique_colors, ColorDistance, Indexed, Lab, LabPixel, Overrides};
+use image::ImageBuffer;
+use rayon::prelude::*;
+
+/// Segments images into SLIC superpixels, regions of similar color in CIELAB,
+/// and maps every region to a single palette color for a flat, illustrated look.
+///
+/// See <https://www.iro.umontreal.ca/~mignotte/IFT6150/Articles/SLIC_Superpixels.pdf>
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Slic {
+    /// The approximate width and height of a region in pixels.
+    pub region_size: u32,
+    /// How much the distance between pixels counts against their color difference,
+    /// higher values give more regular, square regions, lower ones follow edges more closely.
+    pub compactness: f32,
+    /// The number of times the regions are refined.
+    pub iterations: usize,
+}
+
+impl Default for Slic {
+    fn default() -> Self {
+        Self {
+            region_size: 16,
+            compactness: 10.0,
+            iterations: 10,
+        }
+    }
+}
+
+/// The mean color and position of a region.
+#[derive(Copy, Clone, Debug, Default)]
+struct Center {
+    lab: [f32; 3],
+    x: f32,
+    y: f32,
+}
+
+impl Slic {
+    /// Returns the region of every pixel and the mean color of every region.
+    ///
+    /// Regions are numbered in rows of a grid, and regions may end up without any pixels.
+    #[must_use]
+    pub fn segment<P: LabPixel>(
+        &self,
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    ) -> (Vec<usize>, Vec<Lab>) {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let pixels: Vec<Lab> = map_unique_colors::<P, _>(img.as_raw(), true, |lab| *lab);
+        if pixels.is_empty() {
+            return (vec![], vec![]);
+        }
+        let size = (self.region_size as usize).max(1);
+        let (columns, rows) = (width.div_ceil(size), height.div_ceil(size));
+        let color = |i: usize| [pixels[i].l, pixels[i].a, pixels[i].b];
+
+        // start in the middle of every grid cell, moved to the flattest spot next to it
+        // so that regions don't start on an edge or a single noisy pixel
+        let gradient = |x: usize, y: usize| {
+            let difference = |a: usize, b: usize| {
+                let (a, b) = (color(a), color(b));
+                (0..3).map(|i| (a[i] - b[i]).powi(2)).sum::<f32>()
+            };
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+            difference(y * width + left, y * width + right)
+                + difference(up * width + x, down * width + x)
+        };
+        let mut centers: Vec<Center> = (0..rows * columns)
+            .map(|cell| {
+                let x = ((cell % columns) * size + size / 2).min(width - 1);
+                let y = ((cell / columns) * size + size / 2).min(height - 1);
+                let neighbourhood = (y.saturating_sub(1)..=(y + 1).min(height - 1)).flat_map(|y| {
+                    (x.saturating_sub(1)..=(x + 1).min(width - 1)).map(move |x| (x, y))
+                });
+                let (x, y) = neighbourhood
+                    .min_by(|a, b| gradient(a.0, a.1).total_cmp(&gradient(b.0, b.1)))
+                    .unwrap_or((x, y));
+                Center {
+                    lab: color(y * width + x),
+                    x: x as f32,
+                    y: y as f32,
+                }
+            })
+            .collect();
+
+        let spatial_weight = (self.compactness / size as f32).powi(2);
+        let mut labels = vec![0; pixels.len()];
+        for _ in 0..self.iterations.max(1) {
+            // every pixel joins the closest of the regions started in the grid cells around it
+            labels.par_iter_mut().enumerate().for_each(|(i, label)| {
+                let (x, y) = (i % width, i / width);
+                let (column, row) = (x / size, y / size);
+                let pixel = color(i);
+                let mut closest = f32::INFINITY;
+                for row in row.saturating_sub(1)..=(row + 1).min(rows - 1) {
+                    for column in column.saturating_sub(1)..=(column + 1).min(columns - 1) {
+                        let center = &centers[row * columns + column];
+                        let color_distance: f32 =
+                            (0..3).map(|i| (pixel[i] - center.lab[i]).powi(2)).sum();
+                        let (dx, dy) = (x as f32 - center.x, y as f32 - center.y);
+                        let spatial_distance = dx.mul_add(dx, dy * dy);
+                        let distance = spatial_distance.mul_add(spatial_weight, color_distance);
+                        if distance < closest {
+                            closest = distance;
+                            *label = row * columns + column;
+                        }
+                    }
+                }
+            });
+
+            // move every region to the mean color and position of its pixels
+            let sums = labels
+                .par_iter()
+                .enumerate()
+                .fold(
+                    || vec![[0.0f64; 6]; centers.len()],
+                    |mut sums, (i, &label)| {
+                        let pixel = color(i);
+                        let sum = &mut sums[label];
+                        for channel in 0..3 {
+                            sum[channel] += f64::from(pixel[channel]);
+                        }
+                        sum[3] += (i % width) as f64;
+                        sum[4] += (i / width) as f64;
+                        sum[5] += 1.0;
+                        sums
+                    },
+                )
+                .reduce_with(|mut a, b| {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        for (a, b) in a.iter_mut().zip(b) {
+                            *a += b;
+                        }
+                    }
+                    a
+                })
+                .unwrap_or_default();
+            for (center, sum) in centers.iter_mut().zip(sums) {
+                let count = sum[5];
+                // regions that lost all of their pixels stay where they are
+                if count > 0.0 {
+                    center.lab = [0, 1, 2].map(|i| (sum[i] / count) as f32);
+                    center.x = (sum[3] / count) as f32;
+                    center.y = (sum[4] / count) as f32;
+                }
+            }
+        }
+
+        let means = centers
+            .iter()
+            .map(|center| Lab::new(center.lab[0], center.lab[1], center.lab[2], 1.0))
+            .collect();
+        (labels, means)
+    }
+
+    /// Converts the image by mapping the mean color of every region to its closest palette
+    /// color, instead of every pixel on its own. Pixels keep their alpha.
+    #[must_use]
+    pub fn convert<P: LabPixel>(
+        &self,
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+        convert_method: impl ColorDistance,
+        labs: &[Lab],
+    ) -> Indexed<P::Subpixel> {
+        self.convert_with_overrides(img, convert_method, labs, &Overrides::default())
+    }
+
+    /// Like [`Slic::convert`], but regions whose mean color is overridden become
+    /// the palette color of its override.
+    #[must_use]
+    pub fn convert_with_overrides<P: LabPixel>(
+        &self,
+        img: &ImageBuffer<P, Vec<P::Subpixel>>,
+        convert_method: impl ColorDistance,
+        labs: &[Lab],
+        overrides: &Overrides,
+    ) -> Indexed<P::Subpixel> {
+        let (labels, means) = self.segment(img);
+        let find_index = index_finder(convert_method, labs, Some(overrides));
+        let regions: Vec<usize> = means.par_iter().map(&find_index).collect();
+        // the palette color of every region, which takes the alpha of each of its pixels
+        let colors: Vec<Option<[P::Subpixel; 4]>> = regions
+            .iter()
+            .map(|&index| labs.get(index).map(|color| P::from_lab(color).to_rgba().0))
+            .collect();
+        let transparent = P::from_lab(&Lab::default()).to_rgba().0;
+
+        let (indices, pixels): (Vec<u16>, Vec<[P::Subpixel; 4]>) = labels
+            .par_iter()
+            .zip(img.as_raw().par_chunks_exact(4))
+            .map(|(&label, pixel)| {
+                let rgba = colors[label].map_or(transparent, |[r, g, b, _]| [r, g, b, pixel[3]]);
+                (regions[label] as u16, rgba)
+            })
+            .unzip();
+        Indexed {
+            indices,
+            pixels: pixels.concat(),
+        }
+    }
+}